@@ -0,0 +1,202 @@
+use crate::{
+    error::ExcelError,
+    function::{ArgKind, Function, FunctionRegistry},
+    interpreter::ComputeResult,
+    provider::DataProvider,
+};
+
+/// 注册内置函数
+pub fn register_builtins(registry: &mut FunctionRegistry) {
+    registry.register("SUM", aggregate(|nums| Ok(nums.iter().sum())));
+    registry.register(
+        "AVERAGE",
+        aggregate(|nums| {
+            if nums.is_empty() {
+                Err(ExcelError::Div0)
+            } else {
+                Ok(nums.iter().sum::<f32>() / nums.len() as f32)
+            }
+        }),
+    );
+    registry.register(
+        "MIN",
+        aggregate(|nums| Ok(nums.iter().cloned().reduce(f32::min).unwrap_or(0_f32))),
+    );
+    registry.register(
+        "MAX",
+        aggregate(|nums| Ok(nums.iter().cloned().reduce(f32::max).unwrap_or(0_f32))),
+    );
+    registry.register(
+        "COUNT",
+        Function {
+            min_args: 1,
+            max_args: None,
+            arg_kinds: vec![ArgKind::Range],
+            volatile: false,
+            body: Box::new(|args, ctx| {
+                let mut count = 0;
+                for arg in args {
+                    let value = ctx.value(arg)?;
+                    if let Some(items) = items(&value, ctx.provider()) {
+                        count += items
+                            .iter()
+                            .filter(|value| matches!(value, ComputeResult::Number(_)))
+                            .count();
+                        continue;
+                    }
+                    match value {
+                        ComputeResult::Empty | ComputeResult::Error(_) => {}
+                        value => {
+                            if value.as_num().is_ok() {
+                                count += 1;
+                            }
+                        }
+                    }
+                }
+                Ok(ComputeResult::Number(count as f32))
+            }),
+        },
+    );
+    registry.register(
+        "IF",
+        Function {
+            min_args: 2,
+            max_args: Some(3),
+            arg_kinds: vec![ArgKind::Scalar, ArgKind::Lazy],
+            volatile: false,
+            body: Box::new(|args, ctx| match ctx.value(&args[0])?.as_bool() {
+                Ok(true) => ctx.value(&args[1]),
+                Ok(false) => match args.get(2) {
+                    Some(arg) => ctx.value(arg),
+                    None => Ok(ComputeResult::Bool(false)),
+                },
+                Err(e) => Ok(ComputeResult::Error(e)),
+            }),
+        },
+    );
+    registry.register(
+        "IFERROR",
+        Function {
+            min_args: 2,
+            max_args: Some(2),
+            arg_kinds: vec![ArgKind::Scalar, ArgKind::Lazy],
+            volatile: false,
+            body: Box::new(|args, ctx| match ctx.value(&args[0])? {
+                ComputeResult::Error(_) => ctx.value(&args[1]),
+                value => Ok(value),
+            }),
+        },
+    );
+    registry.register(
+        "ISERROR",
+        Function {
+            min_args: 1,
+            max_args: Some(1),
+            arg_kinds: vec![ArgKind::Scalar],
+            volatile: false,
+            body: Box::new(|args, ctx| {
+                let is_error = matches!(ctx.value(&args[0])?, ComputeResult::Error(_));
+                Ok(ComputeResult::Bool(is_error))
+            }),
+        },
+    );
+    registry.register("AND", logical(|bools| bools.iter().all(|b| *b)));
+    registry.register("OR", logical(|bools| bools.iter().any(|b| *b)));
+    registry.register(
+        "NOT",
+        Function {
+            min_args: 1,
+            max_args: Some(1),
+            arg_kinds: vec![ArgKind::Scalar],
+            volatile: false,
+            body: Box::new(|args, ctx| match ctx.value(&args[0])?.as_bool() {
+                Ok(b) => Ok(ComputeResult::Bool(!b)),
+                Err(e) => Ok(ComputeResult::Error(e)),
+            }),
+        },
+    );
+}
+
+/// 区域（或并集中各区域）中的非空单元格或数组中的元素，其他值返回 None
+fn items(value: &ComputeResult, provider: &dyn DataProvider) -> Option<Vec<ComputeResult>> {
+    match value {
+        ComputeResult::Range(range) => Some(range.cells(provider).map(|(_, _, v)| v).collect()),
+        ComputeResult::Union(ranges) => Some(
+            ranges
+                .iter()
+                .flat_map(|range| range.cells(provider).map(|(_, _, v)| v))
+                .collect(),
+        ),
+        ComputeResult::Array(rows) => Some(rows.iter().flatten().cloned().collect()),
+        _ => None,
+    }
+}
+
+/// 对所有参数中的数字做聚合，区域中只统计数字单元格，遇到错误值时返回该错误
+fn aggregate(f: fn(&[f32]) -> Result<f32, ExcelError>) -> Function {
+    Function {
+        min_args: 1,
+        max_args: None,
+        arg_kinds: vec![ArgKind::Range],
+        volatile: false,
+        body: Box::new(move |args, ctx| {
+            let mut nums = vec![];
+            for arg in args {
+                let value = ctx.value(arg)?;
+                if let Some(items) = items(&value, ctx.provider()) {
+                    for value in items {
+                        match value {
+                            ComputeResult::Number(n) => nums.push(n),
+                            ComputeResult::Error(e) => return Ok(ComputeResult::Error(e)),
+                            _ => {}
+                        }
+                    }
+                    continue;
+                }
+                match value {
+                    ComputeResult::Empty => {}
+                    value => match value.as_num() {
+                        Ok(n) => nums.push(n),
+                        Err(e) => return Ok(ComputeResult::Error(e)),
+                    },
+                }
+            }
+            match f(&nums) {
+                Ok(n) => Ok(ComputeResult::Number(n)),
+                Err(e) => Ok(ComputeResult::Error(e)),
+            }
+        }),
+    }
+}
+
+/// 对所有参数中的逻辑值做聚合，区域中忽略文本和空单元格，遇到错误值时返回该错误
+fn logical(f: fn(&[bool]) -> bool) -> Function {
+    Function {
+        min_args: 1,
+        max_args: None,
+        arg_kinds: vec![ArgKind::Range],
+        volatile: false,
+        body: Box::new(move |args, ctx| {
+            let mut bools = vec![];
+            for arg in args {
+                let value = ctx.value(arg)?;
+                if let Some(items) = items(&value, ctx.provider()) {
+                    for value in items {
+                        match value {
+                            ComputeResult::Bool(b) => bools.push(b),
+                            ComputeResult::Number(n) => bools.push(n != 0_f32),
+                            ComputeResult::Error(e) => return Ok(ComputeResult::Error(e)),
+                            _ => {}
+                        }
+                    }
+                    continue;
+                }
+                match value.as_bool() {
+                    Ok(b) => bools.push(b),
+                    Err(e) => return Ok(ComputeResult::Error(e)),
+                }
+            }
+            Ok(ComputeResult::Bool(f(&bools)))
+        }),
+    }
+}
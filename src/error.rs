@@ -0,0 +1,152 @@
+use std::fmt;
+
+/// 公式中的错误值，和数字、文本一样作为计算结果向上传递
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExcelError {
+    Div0,
+    Value,
+    Ref,
+    Name,
+    NA,
+    Num,
+    Null,
+    /// 动态数组的溢出区域被阻挡
+    Spill,
+}
+
+impl ExcelError {
+    pub const ALL: [ExcelError; 8] = [
+        ExcelError::Div0,
+        ExcelError::Value,
+        ExcelError::Ref,
+        ExcelError::Name,
+        ExcelError::NA,
+        ExcelError::Num,
+        ExcelError::Null,
+        ExcelError::Spill,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExcelError::Div0 => "#DIV/0!",
+            ExcelError::Value => "#VALUE!",
+            ExcelError::Ref => "#REF!",
+            ExcelError::Name => "#NAME?",
+            ExcelError::NA => "#N/A",
+            ExcelError::Num => "#NUM!",
+            ExcelError::Null => "#NULL!",
+            ExcelError::Spill => "#SPILL!",
+        }
+    }
+}
+
+impl fmt::Display for ExcelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 公式文本中的区间，以字节偏移表示，左闭右开
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// 同时覆盖两个区间的最小区间
+    pub fn to(&self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// 解析或计算公式时的错误种类
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    UnexpectedChar(char),
+    InvalidNumber,
+    UnterminatedString,
+    UnexpectedToken,
+    UnexpectedEof,
+    UnmatchedParen,
+    /// 区域两端的引用不合法
+    InvalidRange,
+    /// 工作表名称之后不是合法的引用
+    InvalidSheetRef,
+    /// 数组常量中含有常量以外的元素，或各行长度不同
+    InvalidArray,
+    ArgumentCount {
+        name: String,
+        count: usize,
+    },
+    /// 尚不支持的语法
+    Unsupported(String),
+    /// 自定义函数返回的错误
+    Custom(String),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::UnexpectedChar(c) => write!(f, "Unexpected character: {}", c),
+            ErrorKind::InvalidNumber => f.write_str("Invalid number format"),
+            ErrorKind::UnterminatedString => f.write_str("Unterminated string"),
+            ErrorKind::UnexpectedToken => f.write_str("Unexpected token"),
+            ErrorKind::UnexpectedEof => f.write_str("Unexpected EOF"),
+            ErrorKind::UnmatchedParen => f.write_str("Unmatched parenthesis"),
+            ErrorKind::InvalidRange => f.write_str("Range not valid"),
+            ErrorKind::InvalidSheetRef => f.write_str("Not valid cross sheet reference"),
+            ErrorKind::InvalidArray => f.write_str("Array constant not valid"),
+            ErrorKind::ArgumentCount { name, count } => {
+                write!(f, "Wrong number of arguments for {}: {}", name, count)
+            }
+            ErrorKind::Unsupported(what) => write!(f, "Not implemented: {}", what),
+            ErrorKind::Custom(message) => f.write_str(message),
+        }
+    }
+}
+
+/// 公式引擎的错误，span 指向公式中出错的部分
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub span: Option<Span>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, span: Span) -> Error {
+        Error {
+            kind,
+            span: Some(span),
+        }
+    }
+
+    /// 没有位置信息的错误，供自定义函数使用
+    pub fn custom(message: &str) -> Error {
+        Error {
+            kind: ErrorKind::Custom(message.to_string()),
+            span: None,
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error { kind, span: None }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} at {}..{}", self.kind, span.start, span.end),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
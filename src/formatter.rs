@@ -0,0 +1,140 @@
+use crate::{
+    node::{ASTNode, Node},
+    printer::{
+        has_sheet, intersect_min, op_precedence, op_text, precedence, separate_name, to_formula,
+    },
+    token::Token,
+};
+
+/// 格式化选项
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatOptions {
+    /// 每行的最大宽度，超出时将函数参数拆成多行
+    pub width: usize,
+    /// 每层缩进的空格数
+    pub indent: usize,
+    /// 是否将函数名转为大写
+    pub uppercase: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            width: 80,
+            indent: 4,
+            uppercase: true,
+        }
+    }
+}
+
+/// 格式化公式：运算符两侧和逗号之后加空格，一行放不下的函数调用每个参数单独一行
+pub fn format_formula(node: &Node, options: &FormatOptions) -> String {
+    let mut text = String::new();
+    write_node(&mut text, node, 0, true, options);
+    text
+}
+
+/// 当前行已有的宽度
+fn column(text: &str) -> usize {
+    match text.rfind('\n') {
+        Some(i) => text[i + 1..].chars().count(),
+        None => text.chars().count(),
+    }
+}
+
+fn write_operand(
+    text: &mut String,
+    node: &Node,
+    min: u8,
+    depth: usize,
+    wrap: bool,
+    options: &FormatOptions,
+) {
+    if precedence(&node.kind) < min {
+        text.push('(');
+        write_node(text, node, depth, wrap, options);
+        text.push(')');
+    } else {
+        write_node(text, node, depth, wrap, options);
+    }
+}
+
+/// wrap 为 false 时输出在一行内，depth 为当前行的缩进层数
+fn write_node(text: &mut String, node: &Node, depth: usize, wrap: bool, options: &FormatOptions) {
+    match &node.kind {
+        ASTNode::SignedNumber(sign, inner) => {
+            text.push(if *sign < 0_f32 { '-' } else { '+' });
+            write_operand(text, inner, 6, depth, wrap, options);
+        }
+        ASTNode::ImplicitIntersection(inner) => {
+            text.push('@');
+            write_operand(text, inner, 8, depth, wrap, options);
+        }
+        ASTNode::BinaryOp(op, left, right) => {
+            let p = op_precedence(op);
+            let (left_min, right_min) = match op {
+                Token::Intersect => (intersect_min(left, p), intersect_min(right, p + 1)),
+                _ => (p, p + 1),
+            };
+            let start = text.len();
+            write_operand(text, left, left_min, depth, wrap, options);
+            let end = text.len();
+            match op {
+                Token::Pow | Token::Intersect => text.push_str(op_text(op)),
+                Token::Comma => text.push_str(", "),
+                _ => text.push_str(&format!(" {} ", op_text(op))),
+            }
+            write_operand(text, right, right_min, depth, wrap, options);
+            // 交集的右侧以括号开头时，左侧的名称需要括号，如 (Data) (B1, C1)
+            if *op == Token::Intersect && text[end + 1..].starts_with('(') {
+                let rest = text.split_off(end);
+                separate_name(text, start, left);
+                text.push_str(&rest);
+            }
+        }
+        // 区域运算符两侧可以是函数调用，如 INDEX(A:A, 1):B10
+        ASTNode::Range(left, right) => {
+            let start = text.len();
+            write_operand(text, left, 8, depth, wrap, options);
+            if has_sheet(right) {
+                separate_name(text, start, left);
+            }
+            text.push(':');
+            write_operand(text, right, 9, depth, wrap, options);
+        }
+        ASTNode::Call(name, args) => {
+            let name = if options.uppercase {
+                name.to_uppercase()
+            } else {
+                name.clone()
+            };
+            let mut flat = String::new();
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    flat.push_str(", ");
+                }
+                write_operand(&mut flat, arg, 1, depth, false, options);
+            }
+            let flat = format!("{}({})", name, flat);
+            if !wrap || args.is_empty() || column(text) + flat.chars().count() <= options.width {
+                text.push_str(&flat);
+                return;
+            }
+            text.push_str(&name);
+            text.push('(');
+            let indent = " ".repeat((depth + 1) * options.indent);
+            for (i, arg) in args.iter().enumerate() {
+                text.push('\n');
+                text.push_str(&indent);
+                write_operand(text, arg, 1, depth + 1, true, options);
+                if i + 1 < args.len() {
+                    text.push(',');
+                }
+            }
+            text.push('\n');
+            text.push_str(&" ".repeat(depth * options.indent));
+            text.push(')');
+        }
+        _ => text.push_str(&to_formula(node)),
+    }
+}
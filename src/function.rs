@@ -0,0 +1,118 @@
+use crate::{
+    error::{Error, ErrorKind},
+    interpreter::{ComputeResult, Context, Interpreter},
+    node::Node,
+    provider::DataProvider,
+    CellPosition,
+};
+use std::collections::HashMap;
+
+/// 参数的求值方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    /// 求值为单个值，参数为数组或多个单元格的区域时按元素分别调用函数
+    Scalar,
+    /// 保留区域引用，由函数自行遍历，适用于 SUM 之类的聚合函数
+    Range,
+    /// 不预先求值，由函数按需求值，适用于 IF 之类的条件函数
+    Lazy,
+}
+
+/// 传给函数实现的参数
+pub enum Argument<'a> {
+    Value(ComputeResult),
+    Lazy(LazyArg<'a>),
+}
+
+/// 尚未求值的参数，通过 CallContext::evaluate 求值
+pub struct LazyArg<'a>(pub(crate) &'a Node);
+
+/// 函数调用时可以访问的上下文
+pub struct CallContext<'a> {
+    pub(crate) interpreter: &'a Interpreter,
+    pub(crate) ctx: &'a Context<'a>,
+}
+
+impl<'a> CallContext<'a> {
+    pub fn provider(&self) -> &dyn DataProvider {
+        self.ctx.provider
+    }
+
+    pub fn position(&self) -> &CellPosition {
+        self.ctx.position
+    }
+
+    /// 对延迟参数求值
+    pub fn evaluate(&self, arg: &LazyArg) -> Result<ComputeResult, Error> {
+        self.interpreter.evaluate(arg.0, self.ctx)
+    }
+
+    /// 取参数的值，延迟参数会在此时求值
+    pub fn value(&self, arg: &Argument) -> Result<ComputeResult, Error> {
+        match arg {
+            Argument::Value(v) => Ok(v.clone()),
+            Argument::Lazy(lazy) => self.evaluate(lazy),
+        }
+    }
+}
+
+pub type FunctionBody = dyn Fn(&[Argument], &CallContext) -> Result<ComputeResult, Error>;
+
+/// 函数定义
+pub struct Function {
+    pub min_args: usize,
+    /// None 表示不限参数个数
+    pub max_args: Option<usize>,
+    /// 每个参数的求值方式，参数多于此列表时沿用最后一项，列表为空时按 Scalar 处理
+    pub arg_kinds: Vec<ArgKind>,
+    /// 易失函数（如 NOW、RAND）的结果不只取决于参数，每次重算都需要重新求值
+    pub volatile: bool,
+    pub body: Box<FunctionBody>,
+}
+
+impl Function {
+    pub fn arg_kind(&self, index: usize) -> ArgKind {
+        match self.arg_kinds.get(index) {
+            Some(kind) => *kind,
+            None => *self.arg_kinds.last().unwrap_or(&ArgKind::Scalar),
+        }
+    }
+
+    pub fn check_arity(&self, name: &str, count: usize) -> Result<(), Error> {
+        if count < self.min_args || self.max_args.is_some_and(|max| count > max) {
+            return Err(ErrorKind::ArgumentCount {
+                name: name.to_uppercase(),
+                count,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// 函数表，函数名不区分大小写
+#[derive(Default)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, Function>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> FunctionRegistry {
+        FunctionRegistry {
+            functions: HashMap::new(),
+        }
+    }
+
+    /// 注册函数，同名函数会被覆盖
+    pub fn register(&mut self, name: &str, function: Function) {
+        self.functions.insert(name.to_uppercase(), function);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(&name.to_uppercase())
+    }
+
+    pub fn is_volatile(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|f| f.volatile)
+    }
+}
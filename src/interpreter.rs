@@ -1,137 +1,789 @@
-use crate::{lexer::Lexer, node::ASTNode, parser::Parser, token::Token};
-pub struct Interpreter<'a> {
-    pub lexer: Lexer<'a>,
+use crate::{
+    builtins::register_builtins,
+    error::{Error, ErrorKind, ExcelError, Span},
+    function::{ArgKind, Argument, CallContext, Function, FunctionRegistry, LazyArg},
+    lexer::Lexer,
+    names::NameManager,
+    node::{ASTNode, Node},
+    parser::Parser,
+    provider::DataProvider,
+    range::{CellRange, MAX_COLS, MAX_ROWS},
+    reference::CellRef,
+    token::Token,
+    utils::{format_number, same_sheet},
+};
+use std::cmp::Ordering;
+pub struct Interpreter {
+    pub lexer: Lexer,
     parser: Parser,
+    functions: FunctionRegistry,
+    names: NameManager,
+    legacy_arrays: bool,
 }
 
-impl<'a> Interpreter<'a> {
-    pub fn new() -> Interpreter<'a> {
+impl Interpreter {
+    pub fn new() -> Interpreter {
         Interpreter {
             lexer: Lexer::new(),
             parser: Parser::new(),
+            functions: {
+                let mut registry = FunctionRegistry::new();
+                register_builtins(&mut registry);
+                registry
+            },
+            names: NameManager::new(),
+            legacy_arrays: false,
+        }
+    }
+
+    /// 注册自定义函数，函数名不区分大小写，与内置函数同名时覆盖内置函数
+    pub fn register_function(&mut self, name: &str, function: Function) {
+        self.functions.register(name, function);
+    }
+
+    pub fn functions(&self) -> &FunctionRegistry {
+        &self.functions
+    }
+
+    /// 定义名称，formula 为名称引用的区域、常量或公式（不含开头的 =）；
+    /// scope 为工作表名时定义工作表级别的名称
+    pub fn define_name(
+        &mut self,
+        name: &str,
+        scope: Option<&str>,
+        formula: &str,
+    ) -> Result<(), Error> {
+        let node = self.parse(formula)?;
+        self.names.define(name, scope, node);
+        Ok(())
+    }
+
+    pub fn names(&self) -> &NameManager {
+        &self.names
+    }
+
+    pub fn names_mut(&mut self) -> &mut NameManager {
+        &mut self.names
+    }
+
+    /// 使用动态数组之前的计算方式：需要单个值的地方对区域做隐式交集，公式的结果不溢出。
+    /// 用于和旧版 Excel 的工作簿保持一致
+    pub fn set_legacy_arrays(&mut self, legacy: bool) {
+        self.legacy_arrays = legacy;
+    }
+
+    pub fn legacy_arrays(&self) -> bool {
+        self.legacy_arrays
+    }
+
+    /// 隐式交集：区域取与公式所在行或列相交的单元格，不相交时为 #VALUE!；数组取左上角的元素
+    pub fn implicit_intersection(
+        &self,
+        value: ComputeResult,
+        position: &CellPosition,
+        provider: &dyn DataProvider,
+    ) -> ComputeResult {
+        match value {
+            ComputeResult::Range(range) => {
+                let row = if range.rows() == 1 {
+                    Some(range.top)
+                } else {
+                    Some(position.row).filter(|row| (range.top..=range.bottom).contains(row))
+                };
+                let col = if range.cols() == 1 {
+                    Some(range.left)
+                } else {
+                    Some(position.col).filter(|col| (range.left..=range.right).contains(col))
+                };
+                match (row, col) {
+                    (Some(row), Some(col)) => {
+                        range.get(provider, row - range.top, col - range.left)
+                    }
+                    _ => ComputeResult::Error(ExcelError::Value),
+                }
+            }
+            array @ ComputeResult::Array(_) => array_item(&array, 0, 0),
+            ComputeResult::Union(_) => ComputeResult::Error(ExcelError::Value),
+            value => value,
         }
     }
 }
 
-impl<'a> Interpreter<'a> {
+/// 名称的定义中可以引用其他名称，超过该层数时视为名称之间存在循环引用
+pub(crate) const MAX_NAME_DEPTH: usize = 32;
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 求值时的上下文：当前单元格位置与数据来源
+pub(crate) struct Context<'c> {
+    pub(crate) position: &'c CellPosition,
+    pub(crate) provider: &'c dyn DataProvider,
+    /// 正在求值的名称的嵌套层数
+    pub(crate) depth: usize,
+}
+
+impl Interpreter {
     pub fn compute(
         &mut self,
-        input: &'a str,
+        input: &str,
         position: CellPosition,
-    ) -> Result<ComputeResult, String> {
+        provider: &dyn DataProvider,
+    ) -> Result<ComputeResult, Error> {
+        let node = self.parse(input)?;
+        let ctx = Context {
+            position: &position,
+            provider,
+            depth: 0,
+        };
+        self.evaluate(&node, &ctx)
+    }
+
+    /// 将公式拆分为 token，每个 token 附带其在公式中的区间
+    pub fn tokenize(&mut self, input: &str) -> Result<Vec<(Token, Span)>, Error> {
+        self.lexer.make_tokens(input)
+    }
+
+    /// 解析公式，返回的语法树节点均带有其在公式中的区间
+    pub fn parse(&mut self, input: &str) -> Result<Node, Error> {
         let tokens = self.lexer.make_tokens(input)?;
-        let node = self.parser.parse(tokens)?;
-        self.evaluate(&node, &position)
+        self.parser.parse(tokens)
+    }
+
+    /// 对已解析的公式求值
+    pub fn compute_node(
+        &self,
+        node: &Node,
+        position: &CellPosition,
+        provider: &dyn DataProvider,
+    ) -> Result<ComputeResult, Error> {
+        let ctx = Context {
+            position,
+            provider,
+            depth: 0,
+        };
+        self.evaluate(node, &ctx)
     }
 
-    fn evaluate(&self, node: &ASTNode, position: &CellPosition) -> Result<ComputeResult, String> {
-        match node {
+    pub(crate) fn evaluate(&self, node: &Node, ctx: &Context) -> Result<ComputeResult, Error> {
+        match &node.kind {
             ASTNode::Number(num) => Ok(ComputeResult::Number(*num)),
-            ASTNode::SignedNumber(sign, num) => match self.evaluate(num, position)? {
-                ComputeResult::Number(num) => Ok(ComputeResult::Number(sign * num)),
-                ComputeResult::String(str) => Ok(ComputeResult::String(format!("{}{}", sign, str))),
-                _ => Err("Invalid number".to_string()),
+            ASTNode::SignedNumber(sign, num) => match self.evaluate_operand(num, ctx)? {
+                ComputeResult::Array(rows) => Ok(ComputeResult::Array(
+                    rows.into_iter()
+                        .map(|row| row.into_iter().map(|v| apply_sign(*sign, v)).collect())
+                        .collect(),
+                )),
+                value => Ok(apply_sign(*sign, value)),
             },
             ASTNode::String(str) => Ok(ComputeResult::String(str.clone())),
             ASTNode::Bool(bool) => Ok(ComputeResult::Bool(*bool)),
-            ASTNode::BinaryOp(tp, left, right) => self.do_bin_op(tp, left, right, position),
-            _ => Err("Not implemented".to_string()),
+            // 省略的参数和空单元格一样，参与运算时为 0 或空文本
+            ASTNode::Missing => Ok(ComputeResult::Empty),
+            ASTNode::Error(e) => Ok(ComputeResult::Error(*e)),
+            ASTNode::BinaryOp(tp, left, right) => self.do_bin_op(tp, left, right, ctx),
+            ASTNode::Ref(cell, sheet) => Ok(self.resolve_ref(cell, sheet.as_deref(), ctx)),
+            ASTNode::SpillRef(cell, sheet) => {
+                let sheet = sheet.as_deref().unwrap_or(&ctx.position.sheet);
+                match ctx
+                    .provider
+                    .spill_range(sheet, cell.row.index, cell.col.index)
+                {
+                    Some(range) => Ok(ComputeResult::Range(range)),
+                    None => Ok(ComputeResult::Error(ExcelError::Ref)),
+                }
+            }
+            ASTNode::Range(left, right) => match (&left.kind, &right.kind) {
+                // 只有一端指定工作表时，另一端使用同一工作表
+                (ASTNode::Ref(from, s1), ASTNode::Ref(to, s2)) if s1.is_none() || s2.is_none() => {
+                    Ok(self.make_range(
+                        s1.as_ref().or(s2.as_ref()),
+                        from.position(),
+                        to.position(),
+                        ctx,
+                    ))
+                }
+                _ => self.bounding_range(left, right, ctx),
+            },
+            ASTNode::RowRange(from, to, sheet) => {
+                Ok(self.make_range(sheet.as_ref(), (from.index, 1), (to.index, MAX_COLS), ctx))
+            }
+            ASTNode::ColRange(from, to, sheet) => {
+                Ok(self.make_range(sheet.as_ref(), (1, from.index), (MAX_ROWS, to.index), ctx))
+            }
+            ASTNode::Var(name) => {
+                let definition = self.names.resolve(name, &ctx.position.sheet);
+                self.evaluate_name(definition, node.span, ctx, false)
+            }
+            // 指定工作表的名称，如 Sheet1!Total
+            ASTNode::UndeterminedRange(sheet, inner) => match &inner.kind {
+                ASTNode::Var(name) => {
+                    let definition = self.names.get(name, Some(sheet));
+                    self.evaluate_name(definition, node.span, ctx, false)
+                }
+                _ => Ok(ComputeResult::Error(ExcelError::Name)),
+            },
+            ASTNode::Ref3D(first, last, inner) => self.evaluate_3d(first, last, inner, ctx),
+            ASTNode::ImplicitIntersection(inner) => {
+                let value = self.evaluate(inner, ctx)?;
+                Ok(self.implicit_intersection(value, ctx.position, ctx.provider))
+            }
+            ASTNode::Call(name, args) => self.call_function(name, args, node.span, ctx),
+            ASTNode::Array(rows) => {
+                let mut values = Vec::with_capacity(rows.len());
+                for row in rows {
+                    let mut items = Vec::with_capacity(row.len());
+                    for item in row {
+                        items.push(self.evaluate(item, ctx)?);
+                    }
+                    values.push(items);
+                }
+                Ok(ComputeResult::Array(values))
+            }
         }
     }
 
     fn do_bin_op(
         &self,
         token: &Token,
-        left: &ASTNode,
-        right: &ASTNode,
-        position: &CellPosition,
-    ) -> Result<ComputeResult, String> {
-        let left = self.evaluate(left, position)?;
-        let right = self.evaluate(right, position)?;
-        match token {
-            Token::Plus | Token::Minus | Token::Mul | Token::Div => {
-                let left = left.as_num()?;
-                let right = right.as_num()?;
-                match token {
-                    Token::Plus => Ok(ComputeResult::Number(left + right)),
-                    Token::Minus => Ok(ComputeResult::Number(left - right)),
-                    Token::Mul => Ok(ComputeResult::Number(left * right)),
-                    Token::Div => Ok(ComputeResult::Number(left / right)),
-                    _ => Err("Will never enter this arm".to_string()),
-                }
+        left: &Node,
+        right: &Node,
+        ctx: &Context,
+    ) -> Result<ComputeResult, Error> {
+        if let Token::Intersect | Token::Comma = token {
+            return self.reference_op(token, left, right, ctx);
+        }
+        let left = self.evaluate_operand(left, ctx)?;
+        let right = self.evaluate_operand(right, ctx)?;
+        if let (ComputeResult::Array(_), _) | (_, ComputeResult::Array(_)) = (&left, &right) {
+            return broadcast(&left, &right, |l, r| apply_op(token, l, r));
+        }
+        apply_op(token, left, right)
+    }
+
+    /// 三维引用：工作表 first 到 last（含两端）中的同一区域，任意一端的工作表不存在时为 #REF!
+    fn evaluate_3d(
+        &self,
+        first: &str,
+        last: &str,
+        inner: &Node,
+        ctx: &Context,
+    ) -> Result<ComputeResult, Error> {
+        let sheets = ctx.provider.sheet_names();
+        let find = |name: &str| sheets.iter().position(|sheet| same_sheet(sheet, name));
+        let (from, to) = match (find(first), find(last)) {
+            (Some(a), Some(b)) => (a.min(b), a.max(b)),
+            _ => return Ok(ComputeResult::Error(ExcelError::Ref)),
+        };
+        let mut ranges = Vec::with_capacity(to - from + 1);
+        for sheet in &sheets[from..=to] {
+            // 引用中没有工作表，按位于该工作表的公式求值
+            let position = CellPosition {
+                sheet: sheet.clone(),
+                row: ctx.position.row,
+                col: ctx.position.col,
+            };
+            let ctx = Context {
+                position: &position,
+                provider: ctx.provider,
+                depth: ctx.depth,
+            };
+            match self.evaluate_reference(inner, &ctx)? {
+                ComputeResult::Range(range) => ranges.push(range),
+                value => return Ok(value),
             }
-            Token::Gt | Token::Gte | Token::Lt | Token::Lte => {
-                let (left, right) = match (left, right) {
-                    (ComputeResult::Number(l), ComputeResult::Number(r)) => (l, r),
-                    (ComputeResult::String(l), ComputeResult::String(r)) => {
-                        if bigger_string(&l, &r) {
-                            (0_f32, 1_f32)
-                        } else if l == r {
-                            (0_f32, 0_f32)
-                        } else {
-                            (1_f32, 0_f32)
-                        }
-                    }
-                    (ComputeResult::String(_), ComputeResult::Number(_)) => (1_f32, 0_f32),
-                    (ComputeResult::Number(_), ComputeResult::String(_)) => (0_f32, 1_f32),
-                    _ => return Err("Not comparable".to_string()),
+        }
+        match ranges.len() {
+            1 => Ok(ComputeResult::Range(ranges.remove(0))),
+            _ => Ok(ComputeResult::Union(ranges)),
+        }
+    }
+
+    /// 区域运算符：包含两侧所有区域的最小区域，两侧不在同一工作表时为 #VALUE!
+    fn bounding_range(
+        &self,
+        left: &Node,
+        right: &Node,
+        ctx: &Context,
+    ) -> Result<ComputeResult, Error> {
+        let mut ranges = vec![];
+        for side in [left, right] {
+            match areas(self.evaluate_reference(side, ctx)?) {
+                Ok(areas) => ranges.extend(areas),
+                Err(e) => return Ok(ComputeResult::Error(e)),
+            }
+        }
+        let first = ranges[0].clone();
+        match ranges
+            .iter()
+            .try_fold(first, |bounds, range| bounds.bounding(range))
+        {
+            Some(bounds) => Ok(ComputeResult::Range(bounds)),
+            None => Ok(ComputeResult::Error(ExcelError::Value)),
+        }
+    }
+
+    /// 引用的交集与并集，两侧必须是引用，交集为空时为 #NULL!
+    fn reference_op(
+        &self,
+        token: &Token,
+        left: &Node,
+        right: &Node,
+        ctx: &Context,
+    ) -> Result<ComputeResult, Error> {
+        let left = match areas(self.evaluate_reference(left, ctx)?) {
+            Ok(areas) => areas,
+            Err(e) => return Ok(ComputeResult::Error(e)),
+        };
+        let right = match areas(self.evaluate_reference(right, ctx)?) {
+            Ok(areas) => areas,
+            Err(e) => return Ok(ComputeResult::Error(e)),
+        };
+        let result = match token {
+            Token::Intersect => left
+                .iter()
+                .flat_map(|l| right.iter().filter_map(move |r| l.intersect(r)))
+                .collect::<Vec<_>>(),
+            _ => left.into_iter().chain(right).collect(),
+        };
+        match result.len() {
+            0 => Ok(ComputeResult::Error(ExcelError::Null)),
+            1 => Ok(ComputeResult::Range(result.into_iter().next().unwrap())),
+            _ => Ok(ComputeResult::Union(result)),
+        }
+    }
+
+    /// 对名称的定义求值，名称未定义时为 #NAME?；reference 为 true 时保留定义中的单元格引用
+    fn evaluate_name(
+        &self,
+        definition: Option<&Node>,
+        span: Span,
+        ctx: &Context,
+        reference: bool,
+    ) -> Result<ComputeResult, Error> {
+        match definition {
+            Some(definition) if ctx.depth < MAX_NAME_DEPTH => {
+                let ctx = Context {
+                    position: ctx.position,
+                    provider: ctx.provider,
+                    depth: ctx.depth + 1,
+                };
+                let value = if reference {
+                    self.evaluate_reference(definition, &ctx)
+                } else {
+                    self.evaluate(definition, &ctx)
                 };
-                match token {
-                    Token::Gt => Ok(ComputeResult::Bool(left > right)),
-                    Token::Gte => Ok(ComputeResult::Bool(left >= right)),
-                    Token::Lt => Ok(ComputeResult::Bool(left < right)),
-                    Token::Lte => Ok(ComputeResult::Bool(left <= right)),
-                    _ => Err("Will never enter this arm".to_string()),
+                // 定义中的错误位置对当前公式没有意义，改为指向名称
+                value.map_err(|mut e| {
+                    e.span = Some(span);
+                    e
+                })
+            }
+            _ => Ok(ComputeResult::Error(ExcelError::Name)),
+        }
+    }
+
+    /// 读取单元格引用的值，未指定工作表时使用当前单元格所在的工作表
+    fn resolve_ref(&self, cell: &CellRef, sheet: Option<&str>, ctx: &Context) -> ComputeResult {
+        let (row, col) = cell.position();
+        let sheet = sheet.unwrap_or(&ctx.position.sheet);
+        ctx.provider
+            .cell(sheet, row, col)
+            .unwrap_or(ComputeResult::Empty)
+    }
+
+    fn call_function(
+        &self,
+        name: &str,
+        args: &[Node],
+        span: Span,
+        ctx: &Context,
+    ) -> Result<ComputeResult, Error> {
+        let function = match self.functions.get(name) {
+            Some(f) => f,
+            None => return Ok(ComputeResult::Error(ExcelError::Name)),
+        };
+        if let Err(mut e) = function.check_arity(name, args.len()) {
+            e.span = Some(span);
+            return Err(e);
+        }
+        let mut values = Vec::with_capacity(args.len());
+        for (i, arg) in args.iter().enumerate() {
+            values.push(match function.arg_kind(i) {
+                // 数组和多个单元格的区域按元素分别调用函数，旧版计算方式下做隐式交集
+                ArgKind::Scalar if !self.legacy_arrays => {
+                    Argument::Value(self.evaluate_operand(arg, ctx)?)
                 }
+                ArgKind::Scalar => Argument::Value(self.evaluate_scalar(arg, ctx)?),
+                ArgKind::Range => Argument::Value(self.evaluate_reference(arg, ctx)?),
+                ArgKind::Lazy => Argument::Lazy(LazyArg(arg)),
+            });
+        }
+        let call = CallContext {
+            interpreter: self,
+            ctx,
+        };
+        let lifted = values.iter().enumerate().any(|(i, value)| {
+            function.arg_kind(i) == ArgKind::Scalar
+                && matches!(value, Argument::Value(ComputeResult::Array(_)))
+        });
+        let result = if lifted {
+            call_elementwise(function, &values, &call)
+        } else {
+            (function.body)(&values, &call)
+        };
+        // 函数实现返回的错误没有位置信息时，指向整个函数调用
+        result.map_err(|mut e| {
+            e.span.get_or_insert(span);
+            e
+        })
+    }
+
+    /// 求值为运算符的操作数，多个单元格的区域作为数组参与运算，旧版计算方式下做隐式交集
+    fn evaluate_operand(&self, node: &Node, ctx: &Context) -> Result<ComputeResult, Error> {
+        match self.evaluate(node, ctx)? {
+            value @ ComputeResult::Range(_) if self.legacy_arrays => {
+                Ok(self.implicit_intersection(value, ctx.position, ctx.provider))
+            }
+            ComputeResult::Range(range) if range.rows() > 1 || range.cols() > 1 => {
+                Ok(ComputeResult::Array(range.values(ctx.provider)))
             }
-            _ => Err("Should not enter this arm".to_string()),
+            ComputeResult::Range(range) => Ok(range.get(ctx.provider, 0, 0)),
+            value => Ok(value),
+        }
+    }
+
+    /// 求值为单个值，单个单元格的区域取其值
+    fn evaluate_scalar(&self, node: &Node, ctx: &Context) -> Result<ComputeResult, Error> {
+        match self.evaluate(node, ctx)? {
+            value @ ComputeResult::Range(_) if self.legacy_arrays => {
+                Ok(self.implicit_intersection(value, ctx.position, ctx.provider))
+            }
+            ComputeResult::Range(range) => {
+                if range.rows() == 1 && range.cols() == 1 {
+                    Ok(range.get(ctx.provider, 0, 0))
+                } else {
+                    Ok(ComputeResult::Error(ExcelError::Value))
+                }
+            }
+            // 数组取左上角的元素
+            array @ ComputeResult::Array(_) => Ok(array_item(&array, 0, 0)),
+            value => Ok(value),
+        }
+    }
+
+    /// 求值时保留单元格引用，单个单元格也作为区域返回
+    fn evaluate_reference(&self, node: &Node, ctx: &Context) -> Result<ComputeResult, Error> {
+        match &node.kind {
+            ASTNode::Ref(cell, sheet) => {
+                let rc = cell.position();
+                Ok(self.make_range(sheet.as_ref(), rc, rc, ctx))
+            }
+            ASTNode::Var(name) => {
+                let definition = self.names.resolve(name, &ctx.position.sheet);
+                self.evaluate_name(definition, node.span, ctx, true)
+            }
+            ASTNode::UndeterminedRange(sheet, inner) => match &inner.kind {
+                ASTNode::Var(name) => {
+                    let definition = self.names.get(name, Some(sheet));
+                    self.evaluate_name(definition, node.span, ctx, true)
+                }
+                _ => self.evaluate(node, ctx),
+            },
+            _ => self.evaluate(node, ctx),
         }
     }
-}
 
+    /// 创建区域引用，此时并不读取区域内的单元格
+    fn make_range(
+        &self,
+        sheet: Option<&String>,
+        from: (usize, usize),
+        to: (usize, usize),
+        ctx: &Context,
+    ) -> ComputeResult {
+        let sheet = sheet.unwrap_or(&ctx.position.sheet).clone();
+        ComputeResult::Range(CellRange::new(sheet, from, to))
+    }
+}
 
-#[derive(Debug, Clone)]
+/// 公式所在单元格的位置，行列均从 1 开始
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CellPosition {
     pub sheet: String,
     pub row: usize,
     pub col: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ComputeResult {
     Number(f32),
     String(String),
     Bool(bool),
+    Empty,
+    Range(CellRange),
+    /// 多个区域的并集，如 (A1:A3,C1:C3)
+    Union(Vec<CellRange>),
+    Error(ExcelError),
+    /// 数组，按行保存，各行的长度相同
+    Array(Vec<Vec<ComputeResult>>),
 }
 
-fn bigger_string(left: &String, right: &String) -> bool {
-    let chs_r = right.chars().collect::<Vec<char>>();
-    let chs_l = left.chars().collect::<Vec<char>>();
-    let mut i = 0;
-    while i < chs_l.len() {
-        if let Some(ch) = chs_r.get(i) {
-            if (chs_l[i] as u8) < (*ch as u8) {
-                return true;
-            } else if (chs_l[i] as u8) > (*ch as u8) {
-                return false;
+/// 引用包含的区域，错误值原样返回，其他值为 #VALUE!
+fn areas(value: ComputeResult) -> Result<Vec<CellRange>, ExcelError> {
+    match value {
+        ComputeResult::Range(range) => Ok(vec![range]),
+        ComputeResult::Union(ranges) => Ok(ranges),
+        ComputeResult::Error(e) => Err(e),
+        _ => Err(ExcelError::Value),
+    }
+}
+
+/// 对两个单个值做二元运算，错误值优先向上传递，左侧优先
+fn apply_op(
+    token: &Token,
+    left: ComputeResult,
+    right: ComputeResult,
+) -> Result<ComputeResult, Error> {
+    if let ComputeResult::Error(e) = left {
+        return Ok(ComputeResult::Error(e));
+    }
+    if let ComputeResult::Error(e) = right {
+        return Ok(ComputeResult::Error(e));
+    }
+    match token {
+        Token::Plus | Token::Minus | Token::Mul | Token::Div | Token::Pow => {
+            let (left, right) = match (left.as_num(), right.as_num()) {
+                (Ok(l), Ok(r)) => (l, r),
+                (Err(e), _) | (_, Err(e)) => return Ok(ComputeResult::Error(e)),
+            };
+            match token {
+                Token::Plus => Ok(finite(left + right)),
+                Token::Minus => Ok(finite(left - right)),
+                Token::Mul => Ok(finite(left * right)),
+                Token::Div => {
+                    if right == 0_f32 {
+                        Ok(ComputeResult::Error(ExcelError::Div0))
+                    } else {
+                        Ok(finite(left / right))
+                    }
+                }
+                Token::Pow => {
+                    if left == 0_f32 && right == 0_f32 {
+                        Ok(ComputeResult::Error(ExcelError::Num))
+                    } else if left == 0_f32 && right < 0_f32 {
+                        Ok(ComputeResult::Error(ExcelError::Div0))
+                    } else {
+                        Ok(finite(left.powf(right)))
+                    }
+                }
+                _ => Err(ErrorKind::Unsupported(format!("{:?}", token)).into()),
+            }
+        }
+        Token::Ee | Token::Ne | Token::Gt | Token::Gte | Token::Lt | Token::Lte => {
+            let ordering = match compare(&left, &right) {
+                Ok(ordering) => ordering,
+                Err(e) => return Ok(ComputeResult::Error(e)),
+            };
+            match token {
+                Token::Ee => Ok(ComputeResult::Bool(ordering == Ordering::Equal)),
+                Token::Ne => Ok(ComputeResult::Bool(ordering != Ordering::Equal)),
+                Token::Gt => Ok(ComputeResult::Bool(ordering == Ordering::Greater)),
+                Token::Gte => Ok(ComputeResult::Bool(ordering != Ordering::Less)),
+                Token::Lt => Ok(ComputeResult::Bool(ordering == Ordering::Less)),
+                Token::Lte => Ok(ComputeResult::Bool(ordering != Ordering::Greater)),
+                _ => Err(ErrorKind::Unsupported(format!("{:?}", token)).into()),
             }
-        } else {
-            return false;
         }
-        i += 1;
+        Token::And => match (left.as_text(), right.as_text()) {
+            (Ok(l), Ok(r)) => Ok(ComputeResult::String(l + &r)),
+            (Err(e), _) | (_, Err(e)) => Ok(ComputeResult::Error(e)),
+        },
+        _ => Err(ErrorKind::Unsupported(format!("{:?}", token)).into()),
+    }
+}
+
+/// 数组的尺寸（行数, 列数），单个值视为 1x1 的数组
+fn array_size(value: &ComputeResult) -> (usize, usize) {
+    match value {
+        ComputeResult::Array(rows) => (rows.len(), rows.first().map_or(0, |row| row.len())),
+        _ => (1, 1),
+    }
+}
+
+/// 取数组中的元素，单行或单列的数组沿另一方向扩展，超出数组时为 #N/A
+fn array_item(value: &ComputeResult, row: usize, col: usize) -> ComputeResult {
+    match value {
+        ComputeResult::Array(rows) => {
+            let row = if rows.len() == 1 { 0 } else { row };
+            rows.get(row)
+                .and_then(|items| items.get(if items.len() == 1 { 0 } else { col }))
+                .cloned()
+                .unwrap_or(ComputeResult::Error(ExcelError::NA))
+        }
+        value => value.clone(),
+    }
+}
+
+/// 标量参数中有数组时按元素分别调用函数，结果组成数组，尺寸取各数组参数尺寸的较大者。
+/// 每次调用的结果为数组时取对应位置的元素，如 IF({TRUE,FALSE},{1,2},{3,4}) = {1,4}
+fn call_elementwise(
+    function: &Function,
+    args: &[Argument],
+    call: &CallContext,
+) -> Result<ComputeResult, Error> {
+    let lifted = |i| function.arg_kind(i) == ArgKind::Scalar;
+    let (mut rows, mut cols) = (1, 1);
+    for (i, arg) in args.iter().enumerate() {
+        if let Argument::Value(value) = arg {
+            if lifted(i) {
+                let (r, c) = array_size(value);
+                rows = rows.max(r);
+                cols = cols.max(c);
+            }
+        }
+    }
+    let mut result = Vec::with_capacity(rows);
+    for row in 0..rows {
+        let mut items = Vec::with_capacity(cols);
+        for col in 0..cols {
+            let item_args = args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| match arg {
+                    Argument::Value(value) if lifted(i) => {
+                        Argument::Value(array_item(value, row, col))
+                    }
+                    Argument::Value(value) => Argument::Value(value.clone()),
+                    Argument::Lazy(lazy) => Argument::Lazy(LazyArg(lazy.0)),
+                })
+                .collect::<Vec<_>>();
+            let value = (function.body)(&item_args, call)?;
+            items.push(array_item(&value, row, col));
+        }
+        result.push(items);
+    }
+    Ok(ComputeResult::Array(result))
+}
+
+/// 数组之间或数组与单个值之间逐元素运算，结果的尺寸取两侧尺寸的较大者
+fn broadcast(
+    left: &ComputeResult,
+    right: &ComputeResult,
+    f: impl Fn(ComputeResult, ComputeResult) -> Result<ComputeResult, Error>,
+) -> Result<ComputeResult, Error> {
+    let (lr, lc) = array_size(left);
+    let (rr, rc) = array_size(right);
+    let mut rows = Vec::with_capacity(lr.max(rr));
+    for row in 0..lr.max(rr) {
+        let mut items = Vec::with_capacity(lc.max(rc));
+        for col in 0..lc.max(rc) {
+            items.push(f(array_item(left, row, col), array_item(right, row, col))?);
+        }
+        rows.push(items);
+    }
+    Ok(ComputeResult::Array(rows))
+}
+
+/// 正负号先将值转换为数字，如 -"5" = -5、-TRUE = -1
+fn apply_sign(sign: f32, value: ComputeResult) -> ComputeResult {
+    match value.as_num() {
+        // 加 0 避免得到 -0
+        Ok(num) => ComputeResult::Number(sign * num + 0_f32),
+        Err(e) => ComputeResult::Error(e),
+    }
+}
+
+/// 运算结果溢出时为 #NUM!
+fn finite(num: f32) -> ComputeResult {
+    if num.is_finite() {
+        ComputeResult::Number(num)
+    } else {
+        ComputeResult::Error(ExcelError::Num)
+    }
+}
+
+/// 按 Excel 的规则比较两个值：数字 < 文本 < 逻辑值，文本比较不区分大小写
+fn compare(left: &ComputeResult, right: &ComputeResult) -> Result<Ordering, ExcelError> {
+    // 空单元格按另一侧的类型取空值参与比较
+    let (left, right) = match (left, right) {
+        (ComputeResult::Empty, r) => (r.blank_like(), r.clone()),
+        (l, ComputeResult::Empty) => (l.clone(), l.blank_like()),
+        (l, r) => (l.clone(), r.clone()),
+    };
+    match (&left, &right) {
+        (ComputeResult::Number(l), ComputeResult::Number(r)) => {
+            Ok(l.partial_cmp(r).unwrap_or(Ordering::Equal))
+        }
+        (ComputeResult::String(l), ComputeResult::String(r)) => {
+            Ok(l.to_lowercase().cmp(&r.to_lowercase()))
+        }
+        (ComputeResult::Bool(l), ComputeResult::Bool(r)) => Ok(l.cmp(r)),
+        _ => Ok(type_rank(&left)?.cmp(&type_rank(&right)?)),
+    }
+}
+
+fn type_rank(value: &ComputeResult) -> Result<u8, ExcelError> {
+    match value {
+        ComputeResult::Number(_) => Ok(0),
+        ComputeResult::String(_) => Ok(1),
+        ComputeResult::Bool(_) => Ok(2),
+        ComputeResult::Error(e) => Err(*e),
+        _ => Err(ExcelError::Value),
     }
-    return false;
 }
 
 impl ComputeResult {
-    pub fn as_num(&self) -> Result<f32, String> {
+    pub fn as_bool(&self) -> Result<bool, ExcelError> {
+        match self {
+            Self::Bool(b) => Ok(*b),
+            Self::Number(n) => Ok(*n != 0_f32),
+            Self::Empty => Ok(false),
+            Self::String(s) => match s.to_uppercase().as_str() {
+                "TRUE" => Ok(true),
+                "FALSE" => Ok(false),
+                _ => Err(ExcelError::Value),
+            },
+            Self::Error(e) => Err(*e),
+            Self::Range(_) | Self::Union(_) | Self::Array(_) => Err(ExcelError::Value),
+        }
+    }
+
+    /// 转换为文本，数字按常规格式输出
+    pub fn as_text(&self) -> Result<String, ExcelError> {
+        match self {
+            Self::String(s) => Ok(s.clone()),
+            Self::Number(n) => Ok(format_number(*n)),
+            Self::Bool(b) => Ok(if *b { "TRUE" } else { "FALSE" }.to_string()),
+            Self::Empty => Ok(String::new()),
+            Self::Error(e) => Err(*e),
+            Self::Range(_) | Self::Union(_) | Self::Array(_) => Err(ExcelError::Value),
+        }
+    }
+
+    pub fn as_num(&self) -> Result<f32, ExcelError> {
         match self {
             Self::Number(n) => Ok(*n),
+            // 文本 inf、nan 以及超出范围的数字不是有效的数字
             Self::String(s) => match s.parse::<f32>() {
-                Ok(v) => Ok(v),
-                Err(_) => Err(format!("Cannot convert {} to number", s)),
+                Ok(v) if v.is_finite() => Ok(v),
+                _ => Err(ExcelError::Value),
             },
-            _ => Err("Expect a number or string".to_string()),
+            Self::Bool(b) => Ok(if *b { 1_f32 } else { 0_f32 }),
+            Self::Empty => Ok(0_f32),
+            Self::Error(e) => Err(*e),
+            Self::Range(_) | Self::Union(_) | Self::Array(_) => Err(ExcelError::Value),
+        }
+    }
+
+    fn blank_like(&self) -> ComputeResult {
+        match self {
+            Self::String(_) => Self::String(String::new()),
+            Self::Bool(_) => Self::Bool(false),
+            _ => Self::Number(0_f32),
         }
     }
 }
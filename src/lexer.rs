@@ -1,21 +1,24 @@
-use std::str::Chars;
-
-use crate::token::Token;
+use crate::{
+    error::{Error, ErrorKind, ExcelError, Span},
+    reference::CellRef,
+    token::Token,
+};
 use regex::Regex;
-pub struct Lexer<'a> {
-    // pos: usize,
-    chars: Chars<'a>,
+pub struct Lexer {
+    /// 当前字符在输入中的字节偏移
+    pos: usize,
+    input: String,
     cell_reg: Regex,
     // letters_map: String,
     // digits_reg: Regex,
     current: Option<char>,
 }
 
-impl<'a> Lexer<'a> {
-    pub fn new() -> Lexer<'a> {
+impl Lexer {
+    pub fn new() -> Lexer {
         Lexer {
-            // pos: 0,
-            chars: "".chars(),
+            pos: 0,
+            input: String::new(),
             cell_reg: Regex::new(r"^\$?[A-Za-z]+\$?\d+$").unwrap(),
             // letters_map: "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ$".to_string(),
             // digits_reg: Regex::new(r"[0-9]").unwrap(),
@@ -24,23 +27,45 @@ impl<'a> Lexer<'a> {
     }
 }
 
-impl<'a> Lexer<'a> {
-    fn reset(&mut self, input: &'a str) {
-        self.chars = input.chars();
-        // self.pos = 0;
-        self.current = self.chars.nth(0)
+impl Default for Lexer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lexer {
+    fn reset(&mut self, input: &str) {
+        self.input.clear();
+        self.input.push_str(input);
+        self.pos = 0;
+        self.current = self.input.chars().next()
     }
 
     fn advance(&mut self) {
-        // self.pos += 1;
-        self.current = self.chars.nth(0);
+        if let Some(c) = self.current {
+            self.pos += c.len_utf8();
+        }
+        self.current = self.input[self.pos..].chars().next();
     }
 
-    pub fn make_tokens(&mut self, input: &'a str) -> Result<Vec<Token>, String> {
+    /// 从 start 到当前字符之前的区间
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(start, self.pos)
+    }
+
+    /// 当前字符所在的区间
+    fn current_span(&self) -> Span {
+        Span::new(self.pos, self.pos + self.current.map_or(0, |c| c.len_utf8()))
+    }
+
+    pub fn make_tokens(&mut self, input: &str) -> Result<Vec<(Token, Span)>, Error> {
         self.reset(input);
         let mut tokens = Vec::with_capacity(input.len());
+        let mut spans = Vec::with_capacity(input.len());
         while let Some(c) = self.current {
-            if c == ' ' {
+            let start = self.pos;
+            // 公式中可以换行，格式化后的公式也能重新解析
+            if c.is_whitespace() {
                 self.advance();
                 continue;
             } else if c == '(' {
@@ -61,6 +86,18 @@ impl<'a> Lexer<'a> {
             } else if c == '/' {
                 tokens.push(Token::Div);
                 self.advance();
+            } else if c == '^' {
+                tokens.push(Token::Pow);
+                self.advance();
+            } else if c == '{' {
+                tokens.push(Token::Lbrace);
+                self.advance();
+            } else if c == '}' {
+                tokens.push(Token::Rbrace);
+                self.advance();
+            } else if c == ';' {
+                tokens.push(Token::Semicolon);
+                self.advance();
             } else if c == ',' {
                 tokens.push(Token::Comma);
                 self.advance();
@@ -76,74 +113,117 @@ impl<'a> Lexer<'a> {
             } else if c == '&' {
                 tokens.push(Token::And);
                 self.advance();
+            } else if c == '@' {
+                tokens.push(Token::At);
+                self.advance();
             } else if c == '<' {
                 tokens.push(self.make_lt());
             } else if c == '>' {
                 tokens.push(self.make_gt());
+            } else if c == '#' {
+                // A1# 为溢出区域的引用，否则是错误值
+                let after_ref = matches!(tokens.last(), Some(Token::Ref(_)))
+                    && spans.last().is_some_and(|span: &Span| span.end == start);
+                if after_ref {
+                    tokens.push(Token::Spill);
+                    self.advance();
+                } else {
+                    tokens.push(self.make_error()?);
+                }
             } else if c == '"' || c == '\'' {
                 match self.make_str(c) {
                     Ok(token) => tokens.push(token),
                     Err(reason) => return Err(reason),
                 }
-            } else if c.is_digit(10) {
+            } else if c.is_ascii_digit() {
                 tokens.push(self.make_num()?)
-            } else if c == '$' || c.is_ascii_alphabetic() {
+            } else if c == '$' || c == '_' || c == '\\' || c.is_alphabetic() {
                 tokens.push(self.make_identifier());
             } else {
-                return Err(format!("Unexpected character: {}", c));
+                return Err(Error::new(ErrorKind::UnexpectedChar(c), self.current_span()));
             }
+            spans.push(self.span_from(start));
+            self.merge_sheet_span(&mut tokens, &mut spans);
         }
-        Ok(tokens)
+        Ok(mark_intersections(tokens.into_iter().zip(spans).collect()))
     }
 
-    fn make_num(&mut self) -> Result<Token, String> {
+    /// 三维引用的工作表范围（如 Jan:Dec!）合并为一个工作表名 Jan:Dec。
+    /// 带引号的范围（如 'Q1:Q4'!）本身就是一个 token；工作表名中不能出现 :，因此不会混淆
+    fn merge_sheet_span(&self, tokens: &mut Vec<Token>, spans: &mut Vec<Span>) {
+        let n = tokens.len();
+        if n < 3 || (n > 3 && tokens[n - 4].matches(&Token::Csref)) {
+            return;
+        }
+        if let [Token::Var(first), Token::Colon, Token::Sheet(last)] = &tokens[n - 3..] {
+            if spans[n - 3].end == spans[n - 2].start && spans[n - 2].end == spans[n - 1].start {
+                let sheet = Token::Sheet(format!("{}:{}", first, last));
+                let span = Span::new(spans[n - 3].start, spans[n - 1].end);
+                tokens.truncate(n - 3);
+                spans.truncate(n - 3);
+                tokens.push(sheet);
+                spans.push(span);
+            }
+        }
+    }
+
+    fn make_num(&mut self) -> Result<Token, Error> {
+        let start = self.pos;
         let mut num_str = String::new();
         let mut dot_count = 0;
         while let Some(c) = self.current {
-            if c.is_digit(10) {
+            if c.is_ascii_digit() {
                 num_str.push(c);
                 self.advance();
             } else if c == '.' {
                 if dot_count != 0 {
-                    return Err(format!("Invalid number format: {}", num_str));
+                    return Err(Error::new(ErrorKind::InvalidNumber, self.span_from(start)));
                 }
                 dot_count += 1;
                 num_str.push(c);
                 self.advance();
             } else if c == 'E' || c == 'e' {
+                // 科学计数法，指数部分为可选的正负号加整数
+                num_str.push('e');
                 self.advance();
-                let mut f = 1_f32;
-                if let Some(c) = self.current {
-                    if c == '-' || c == '+' {
-                        if c == '-' {
-                            f = -1_f32;
-                        }
-                        self.advance();
-                    }
-                    if let Token::Number(n) = self.make_num()? {
-                        return Ok(Token::Number(
-                            num_str.parse::<f32>().unwrap() * (10_f32).powf(f * n),
-                        ));
-                    }
-                    return Err(format!("Invalid number format: {}", num_str));
-                } else {
-                    return Err(format!("Invalid number format: {}", num_str));
+                if let Some(sign @ ('-' | '+')) = self.current {
+                    num_str.push(sign);
+                    self.advance();
+                }
+                let digits = num_str.len();
+                while let Some(c) = self.current.filter(|c| c.is_ascii_digit()) {
+                    num_str.push(c);
+                    self.advance();
                 }
+                if num_str.len() == digits {
+                    return Err(Error::new(ErrorKind::InvalidNumber, self.span_from(start)));
+                }
+                return Ok(Token::Number(self.parse_num(&num_str, start)?));
             } else if c == '%' {
                 self.advance();
-                return Ok(Token::Number(num_str.parse::<f32>().unwrap() / 100_f32));
+                return Ok(Token::Number(self.parse_num(&num_str, start)? / 100_f32));
             } else {
                 break;
             }
         }
-        return Ok(Token::Number(num_str.parse::<f32>().unwrap()));
+        return Ok(Token::Number(self.parse_num(&num_str, start)?));
+    }
+
+    /// 解析数字文本，无法解析或超出范围（如 1E40）时为 InvalidNumber，区间从 start 到当前字符之前
+    fn parse_num(&self, text: &str, start: usize) -> Result<f32, Error> {
+        match text.parse::<f32>() {
+            Ok(num) if num.is_finite() => Ok(num),
+            _ => Err(Error::new(ErrorKind::InvalidNumber, self.span_from(start))),
+        }
     }
 
+    /// 单元格地址、函数名、名称或工作表名。名称以字母（包括非 ASCII 字母）、下划线或反斜杠开头，
+    /// 之后可以是字母、数字、下划线、点和反斜杠；只有形如 A1、$A$1 的才是单元格地址
     fn make_identifier(&mut self) -> Token {
         let mut ident_str = String::new();
         while let Some(c) = self.current {
-            if c == '$' || c.is_ascii_alphabetic()
-                || (ident_str != "" && c.is_digit(10))
+            if c == '$' || c == '_' || c == '\\' || c.is_alphabetic()
+                || (!ident_str.is_empty() && (c == '.' || c.is_numeric()))
             {
                 ident_str.push(c);
                 self.advance();
@@ -151,9 +231,9 @@ impl<'a> Lexer<'a> {
                 break;
             }
         }
-        if ident_str == "TRUE" {
+        if ident_str.eq_ignore_ascii_case("TRUE") {
             return Token::Bool(true);
-        } else if ident_str == "FALSE" {
+        } else if ident_str.eq_ignore_ascii_case("FALSE") {
             return Token::Bool(false);
         } else if let Some(c) = self.current {
             if c == '!' {
@@ -161,10 +241,27 @@ impl<'a> Lexer<'a> {
             }
         }
         if self.cell_reg.is_match(&ident_str) {
-            return Token::Ref(ident_str.replace("$", ""));
-        } else {
-            return Token::Var(ident_str);
+            // 超出工作表范围的地址（如 A0、XFE1）按名称处理
+            if let Some(cell) = CellRef::parse(&ident_str) {
+                return Token::Ref(cell);
+            }
+        }
+        return Token::Var(ident_str);
+    }
+
+    fn make_error(&mut self) -> Result<Token, Error> {
+        // 从当前的 # 开始需要和某个错误值完全匹配
+        let rest = self.input[self.pos..].to_uppercase();
+        for error in ExcelError::ALL {
+            let literal = error.as_str();
+            if rest.starts_with(literal) {
+                for _ in 0..literal.len() {
+                    self.advance();
+                }
+                return Ok(Token::Error(error));
+            }
         }
+        return Err(Error::new(ErrorKind::UnexpectedChar('#'), self.current_span()));
     }
 
     fn make_gt(&mut self) -> Token {
@@ -203,12 +300,19 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn make_str(&mut self, end: char) -> Result<Token, String> {
+    /// 双引号括起的文本或单引号括起的工作表名，连续两个引号表示引号本身
+    fn make_str(&mut self, end: char) -> Result<Token, Error> {
+        let start = self.pos;
         let mut str_str = String::new();
         self.advance(); // skip leading char
         while let Some(c) = self.current {
             if c == end {
                 self.advance();
+                if self.current == Some(end) {
+                    str_str.push(end);
+                    self.advance();
+                    continue;
+                }
                 if end == '"' {
                     return Ok(Token::String(str_str));
                 } else {
@@ -219,6 +323,33 @@ impl<'a> Lexer<'a> {
                 self.advance();
             }
         }
-        return Err(format!("Invalid string format: {}", str_str));
+        return Err(Error::new(ErrorKind::UnterminatedString, self.span_from(start)));
+    }
+}
+
+/// 引用之间的空格是交集运算符，其余空格只用于分隔。
+/// 函数名与左括号之间的空格不视为交集；数字只有作为整行区域（如 1:3）的一端时才是引用
+fn mark_intersections(tokens: Vec<(Token, Span)>) -> Vec<(Token, Span)> {
+    let mut result = Vec::with_capacity(tokens.len());
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        if i > 0 {
+            let (last, last_span) = &tokens[i - 1];
+            let ends_operand = match last {
+                Token::Ref(_) | Token::Spill | Token::Var(_) | Token::Rparen => true,
+                Token::Number(_) => i > 1 && tokens[i - 2].0.matches(&Token::Colon),
+                _ => false,
+            };
+            let starts_operand = match token {
+                Token::Ref(_) | Token::Var(_) | Token::Sheet(_) | Token::At => true,
+                Token::Number(_) => tokens.get(i + 1).is_some_and(|(t, _)| t.matches(&Token::Colon)),
+                Token::Lparen => !matches!(last, Token::Var(_)),
+                _ => false,
+            };
+            if last_span.end < span.start && ends_operand && starts_operand {
+                result.push((Token::Intersect, Span::new(last_span.end, span.start)));
+            }
+        }
+        result.push((token.clone(), *span));
     }
+    result
 }
@@ -1,33 +1,1462 @@
+#![allow(clippy::needless_return)]
+
+mod builtins;
+mod error;
+mod formatter;
+mod function;
 mod interpreter;
 mod lexer;
+mod names;
 mod node;
 mod parser;
+mod printer;
+mod provider;
+mod range;
+mod reference;
+mod rewrite;
 mod token;
 mod utils;
+mod workbook;
 
-pub use crate::interpreter::{CellPosition, Interpreter};
+pub use crate::error::{Error, ErrorKind, ExcelError, Span};
+pub use crate::formatter::{format_formula, FormatOptions};
+pub use crate::function::{ArgKind, Argument, CallContext, Function, FunctionRegistry, LazyArg};
+pub use crate::interpreter::{CellPosition, ComputeResult, Interpreter};
+pub use crate::names::NameManager;
+pub use crate::node::{ASTNode, Node};
+pub use crate::printer::to_formula;
+pub use crate::provider::DataProvider;
+pub use crate::range::CellRange;
+pub use crate::reference::{AxisRef, CellRef};
+pub use crate::rewrite::{apply_edit, delete_sheet, relocate, rename_sheet, StructuralEdit};
+pub use crate::token::Token;
+pub use crate::workbook::{CalcSettings, Workbook};
 
 #[cfg(test)]
 mod tests {
-    use crate::interpreter::{CellPosition, Interpreter};
+    use crate::error::{Error, ErrorKind, ExcelError, Span};
+    use crate::formatter::{format_formula, FormatOptions};
+    use crate::function::{ArgKind, Function};
+    use crate::interpreter::{CellPosition, ComputeResult, Interpreter};
+    use crate::node::ASTNode;
+    use crate::printer::to_formula;
+    use crate::provider::DataProvider;
+    use crate::range::CellRange;
+    use crate::reference::{AxisRef, CellRef};
+    use crate::rewrite::{apply_edit, delete_sheet, relocate, rename_sheet, StructuralEdit};
+    use crate::token::Token;
+    use crate::utils::quote_sheet_name;
+    use crate::workbook::{CalcSettings, Workbook};
+    use std::collections::HashMap;
     use std::time::Instant;
 
+    /// 测试用的数据来源，键为 (工作表, 行, 列)
+    struct Grid(HashMap<(String, usize, usize), ComputeResult>);
+
+    impl Grid {
+        fn new(cells: &[(&str, usize, usize, ComputeResult)]) -> Grid {
+            Grid(
+                cells
+                    .iter()
+                    .map(|(s, r, c, v)| ((s.to_string(), *r, *c), v.clone()))
+                    .collect(),
+            )
+        }
+    }
+
+    impl DataProvider for Grid {
+        fn cell(&self, sheet: &str, row: usize, col: usize) -> Option<ComputeResult> {
+            self.0.get(&(sheet.to_string(), row, col)).cloned()
+        }
+
+        fn used_range(&self, sheet: &str) -> (usize, usize) {
+            self.0
+                .keys()
+                .filter(|(s, _, _)| s == sheet)
+                .fold((0, 0), |(mr, mc), (_, r, c)| (mr.max(*r), mc.max(*c)))
+        }
+    }
+
+    fn position() -> CellPosition {
+        at(1, 1)
+    }
+
+    /// Sheet1 中的单元格
+    fn at(row: usize, col: usize) -> CellPosition {
+        CellPosition {
+            sheet: "Sheet1".to_string(),
+            row,
+            col,
+        }
+    }
+
     #[test]
     fn compute() {
         let now = Instant::now();
         let mut itp = Interpreter::new();
+        let grid = Grid::new(&[]);
         let mut i = 0;
         while i < 10 {
             let input = "1+2+3+4+5+6+7+8+9+10";
-            let position = CellPosition {
-                sheet: "Sheet1".to_string(),
-                row: 1,
-                col: 1,
-            };
-            let _ = itp.compute(input, position);
+            let _ = itp.compute(input, position(), &grid);
             i += 1;
         }
         println!("{:?}", now.elapsed());
         // println!("{:?}", result);
     }
+
+    #[test]
+    fn cell_reference() {
+        let mut itp = Interpreter::new();
+        let grid = Grid::new(&[
+            ("Sheet1", 1, 2, ComputeResult::Number(3_f32)),
+            ("Sheet2", 2, 1, ComputeResult::Number(4_f32)),
+        ]);
+        assert_eq!(
+            itp.compute("B1*2+Sheet2!A2", position(), &grid),
+            Ok(ComputeResult::Number(10_f32))
+        );
+        assert_eq!(
+            itp.compute("$C$9+1", position(), &grid),
+            Ok(ComputeResult::Number(1_f32))
+        );
+    }
+
+    #[test]
+    fn range_reference() {
+        let mut itp = Interpreter::new();
+        let grid = Grid::new(&[
+            ("Sheet1", 1, 1, ComputeResult::Number(1_f32)),
+            ("Sheet1", 3, 2, ComputeResult::Number(2_f32)),
+            ("Sheet2", 5, 3, ComputeResult::Number(3_f32)),
+        ]);
+        let range = |sheet: &str, top, left, bottom, right| {
+            Ok(ComputeResult::Range(CellRange {
+                sheet: sheet.to_string(),
+                top,
+                left,
+                bottom,
+                right,
+            }))
+        };
+        assert_eq!(
+            itp.compute("B3:A1", position(), &grid),
+            range("Sheet1", 1, 1, 3, 2)
+        );
+        assert_eq!(
+            itp.compute("2:3", position(), &grid),
+            range("Sheet1", 2, 1, 3, 16384)
+        );
+        assert_eq!(
+            itp.compute("Sheet2!B:C", position(), &grid),
+            range("Sheet2", 1, 2, 1048576, 3)
+        );
+        if let Ok(ComputeResult::Range(r)) = itp.compute("A:B", position(), &grid) {
+            let cells = r.cells(&grid).collect::<Vec<_>>();
+            assert_eq!(
+                cells,
+                vec![
+                    (1, 1, ComputeResult::Number(1_f32)),
+                    (3, 2, ComputeResult::Number(2_f32))
+                ]
+            );
+        } else {
+            panic!("Expect a range");
+        }
+    }
+
+    #[test]
+    fn function_call() {
+        let mut itp = Interpreter::new();
+        let grid = Grid::new(&[
+            ("Sheet1", 1, 1, ComputeResult::Number(1_f32)),
+            ("Sheet1", 2, 1, ComputeResult::Number(2_f32)),
+            ("Sheet1", 3, 1, ComputeResult::String("x".to_string())),
+        ]);
+        assert_eq!(
+            itp.compute("sum(A1:A3, 3) + Max(A:A)", position(), &grid),
+            Ok(ComputeResult::Number(8_f32))
+        );
+        // 未选中的分支不会被求值
+        assert_eq!(
+            itp.compute("IF(A1>0, \"yes\", NOPE())", position(), &grid),
+            Ok(ComputeResult::String("yes".to_string()))
+        );
+        assert_eq!(
+            itp.compute("NOPE()", position(), &grid),
+            Ok(ComputeResult::Error(ExcelError::Name))
+        );
+        assert!(itp.compute("NOT(1, 2)", position(), &grid).is_err());
+        // 省略的参数同样计入参数个数，求值时为空
+        assert_eq!(
+            itp.compute("IF(A1,,1)", position(), &grid),
+            Ok(ComputeResult::Empty)
+        );
+        assert_eq!(
+            itp.compute("IF(A1,,1)+SUM(A1,,A2)", position(), &grid),
+            Ok(ComputeResult::Number(3_f32))
+        );
+        assert!(itp.compute("IF(A1,1,2,)", position(), &grid).is_err());
+        assert!(itp.compute("NOT()", position(), &grid).is_err());
+
+        itp.register_function(
+            "double",
+            Function {
+                min_args: 1,
+                max_args: Some(1),
+                arg_kinds: vec![ArgKind::Scalar],
+                volatile: true,
+                body: Box::new(|args, ctx| match ctx.value(&args[0])?.as_num() {
+                    Ok(n) => Ok(ComputeResult::Number(n * 2_f32)),
+                    Err(e) => Ok(ComputeResult::Error(e)),
+                }),
+            },
+        );
+        assert!(itp.functions().is_volatile("DOUBLE"));
+        assert_eq!(
+            itp.compute("DOUBLE(A2)", position(), &grid),
+            Ok(ComputeResult::Number(4_f32))
+        );
+    }
+
+    #[test]
+    fn error_values() {
+        let mut itp = Interpreter::new();
+        let grid = Grid::new(&[
+            ("Sheet1", 1, 1, ComputeResult::String("x".to_string())),
+            ("Sheet1", 2, 1, ComputeResult::Error(ExcelError::NA)),
+        ]);
+        let error = |e| Ok(ComputeResult::Error(e));
+        assert_eq!(
+            itp.compute("1/0", position(), &grid),
+            error(ExcelError::Div0)
+        );
+        assert_eq!(
+            itp.compute("A1*2", position(), &grid),
+            error(ExcelError::Value)
+        );
+        assert_eq!(
+            itp.compute("SUM(A1:A2)+1", position(), &grid),
+            error(ExcelError::NA)
+        );
+        assert_eq!(
+            itp.compute("#n/a + 1/0", position(), &grid),
+            error(ExcelError::NA)
+        );
+        assert_eq!(
+            itp.compute("-#DIV/0!", position(), &grid),
+            error(ExcelError::Div0)
+        );
+        assert_eq!(
+            itp.compute("IFERROR(#REF!, 3)", position(), &grid),
+            Ok(ComputeResult::Number(3_f32))
+        );
+        assert!(itp.compute("#FOO!", position(), &grid).is_err());
+
+        // 正负号和算术运算一样先转换为数字
+        let num = |n| Ok(ComputeResult::Number(n));
+        assert_eq!(itp.compute("-\"5\"", position(), &grid), num(-5_f32));
+        assert_eq!(itp.compute("-TRUE", position(), &grid), num(-1_f32));
+        assert_eq!(
+            itp.compute("-\"abc\"", position(), &grid),
+            error(ExcelError::Value)
+        );
+        assert_eq!(
+            itp.compute("\"inf\"*1", position(), &grid),
+            error(ExcelError::Value)
+        );
+        assert_eq!(
+            itp.compute("\"nan\"+1", position(), &grid),
+            error(ExcelError::Value)
+        );
+        assert_eq!(
+            itp.compute("\"1E40\"+1", position(), &grid),
+            error(ExcelError::Value)
+        );
+        assert_eq!(
+            itp.compute("1E30*1E30", position(), &grid),
+            error(ExcelError::Num)
+        );
+    }
+
+    #[test]
+    fn equality_and_concat() {
+        let mut itp = Interpreter::new();
+        let grid = Grid::new(&[("Sheet1", 1, 1, ComputeResult::Number(0.5_f32))]);
+        let eval = |itp: &mut Interpreter, input| itp.compute(input, position(), &grid);
+        let yes = Ok(ComputeResult::Bool(true));
+        assert_eq!(eval(&mut itp, "\"abc\"=\"ABC\""), yes);
+        assert_eq!(eval(&mut itp, "1<>\"1\""), yes);
+        assert_eq!(eval(&mut itp, "\"a\"<\"AB\""), yes);
+        assert_eq!(eval(&mut itp, "TRUE>\"z\""), yes);
+        assert_eq!(eval(&mut itp, "true=NOT(False)"), yes);
+        assert_eq!(eval(&mut itp, "B1=0"), yes);
+        assert_eq!(eval(&mut itp, "B1=\"\""), yes);
+        assert_eq!(
+            eval(&mut itp, "A1&\"-\"&TRUE&B1&2E20&1/4"),
+            Ok(ComputeResult::String("0.5-TRUE2E+200.25".to_string()))
+        );
+        assert_eq!(
+            eval(&mut itp, "\"a\"&#N/A"),
+            Ok(ComputeResult::Error(ExcelError::NA))
+        );
+    }
+
+    #[test]
+    fn power() {
+        let mut itp = Interpreter::new();
+        let grid = Grid::new(&[]);
+        let num = |n| Ok(ComputeResult::Number(n));
+        assert_eq!(itp.compute("2^10", position(), &grid), num(1024_f32));
+        assert_eq!(itp.compute("-2^2", position(), &grid), num(4_f32));
+        assert_eq!(itp.compute("2^3^2", position(), &grid), num(64_f32));
+        assert_eq!(itp.compute("2*3^2", position(), &grid), num(18_f32));
+        assert_eq!(itp.compute("2^-1", position(), &grid), num(0.5_f32));
+        // 连续的正负号，-- 常用于将逻辑值转换为数字
+        assert_eq!(itp.compute("--TRUE", position(), &grid), num(1_f32));
+        assert_eq!(itp.compute("-+-2^2", position(), &grid), num(4_f32));
+        assert_eq!(itp.compute("2*--1", position(), &grid), num(2_f32));
+        assert_eq!(
+            itp.compute("(-8)^0.5", position(), &grid),
+            Ok(ComputeResult::Error(ExcelError::Num))
+        );
+    }
+
+    #[test]
+    fn error_position() {
+        let mut itp = Interpreter::new();
+        let grid = Grid::new(&[]);
+        let error = |kind, start, end| Err(Error::new(kind, Span::new(start, end)));
+        assert_eq!(
+            itp.compute("1 + )", position(), &grid),
+            error(ErrorKind::UnexpectedToken, 4, 5)
+        );
+        assert_eq!(
+            itp.compute("1 2", position(), &grid),
+            error(ErrorKind::UnexpectedToken, 2, 3)
+        );
+        assert_eq!(
+            itp.compute("SUM(1 2)", position(), &grid),
+            error(ErrorKind::UnexpectedToken, 6, 7)
+        );
+        assert_eq!(
+            itp.compute("SUM(1(2))", position(), &grid),
+            error(ErrorKind::UnexpectedToken, 5, 6)
+        );
+        assert_eq!(
+            itp.compute("1e+", position(), &grid),
+            error(ErrorKind::InvalidNumber, 0, 3)
+        );
+        assert_eq!(
+            itp.compute("1ex", position(), &grid),
+            error(ErrorKind::InvalidNumber, 0, 2)
+        );
+        assert_eq!(
+            itp.compute("1+1E40", position(), &grid),
+            error(ErrorKind::InvalidNumber, 2, 6)
+        );
+        assert_eq!(
+            itp.compute("SUM(1, (2)", position(), &grid),
+            error(ErrorKind::UnmatchedParen, 3, 4)
+        );
+        assert_eq!(
+            itp.compute("1 & \"abc", position(), &grid),
+            error(ErrorKind::UnterminatedString, 4, 8)
+        );
+        assert_eq!(
+            itp.compute("\"数\" + ?", position(), &grid),
+            error(ErrorKind::UnexpectedChar('?'), 8, 9)
+        );
+        assert_eq!(
+            itp.compute("1+A1:3", position(), &grid),
+            error(ErrorKind::InvalidRange, 2, 6)
+        );
+        assert_eq!(
+            itp.compute("1*", position(), &grid),
+            error(ErrorKind::UnexpectedEof, 2, 2)
+        );
+        let err: Box<dyn std::error::Error> =
+            Box::new(itp.compute("1.2.3", position(), &grid).unwrap_err());
+        assert_eq!(err.to_string(), "Invalid number format at 0..3");
+    }
+
+    #[test]
+    fn node_span() {
+        let mut itp = Interpreter::new();
+        let input = "SUM(A1, 2 * Sheet2!B3:C4) + 1";
+        assert_eq!(
+            itp.tokenize("A1+ 20").unwrap(),
+            vec![
+                (Token::Ref(CellRef::parse("A1").unwrap()), Span::new(0, 2)),
+                (Token::Plus, Span::new(2, 3)),
+                (Token::Number(20_f32), Span::new(4, 6)),
+            ]
+        );
+        let node = itp.parse(input).unwrap();
+        assert_eq!(node.span, Span::new(0, 29));
+        let call = match node.kind {
+            ASTNode::BinaryOp(_, left, _) => left,
+            _ => panic!("Expect a binary operation"),
+        };
+        assert_eq!(
+            &input[call.span.start..call.span.end],
+            "SUM(A1, 2 * Sheet2!B3:C4)"
+        );
+        if let ASTNode::Call(_, args) = &call.kind {
+            let texts = args
+                .iter()
+                .map(|arg| &input[arg.span.start..arg.span.end])
+                .collect::<Vec<_>>();
+            assert_eq!(texts, vec!["A1", "2 * Sheet2!B3:C4"]);
+            if let ASTNode::BinaryOp(_, _, range) = &args[1].kind {
+                assert_eq!(&input[range.span.start..range.span.end], "Sheet2!B3:C4");
+            }
+        }
+        assert_eq!(
+            itp.compute("1 + NOT(1, 2)", position(), &Grid::new(&[])),
+            Err(Error::new(
+                ErrorKind::ArgumentCount {
+                    name: "NOT".to_string(),
+                    count: 2
+                },
+                Span::new(4, 13)
+            ))
+        );
+    }
+
+    #[test]
+    fn incremental_recalculation() {
+        let mut book = Workbook::new();
+        book.set_value(at(1, 1), ComputeResult::Number(1_f32));
+        book.set_formula(at(1, 2), "A1*2").unwrap();
+        book.set_formula(at(1, 3), "SUM(A1:B1)").unwrap();
+        book.set_formula(at(2, 1), "C1+B1").unwrap();
+        book.set_formula(at(9, 9), "1+1").unwrap();
+        assert_eq!(book.value(&at(2, 1)), ComputeResult::Number(5_f32));
+
+        let order = book.set_value(at(1, 1), ComputeResult::Number(5_f32));
+        assert_eq!(order, vec![at(1, 2), at(1, 3), at(2, 1)]);
+        assert_eq!(book.value(&at(1, 3)), ComputeResult::Number(15_f32));
+        assert_eq!(book.value(&at(2, 1)), ComputeResult::Number(25_f32));
+
+        // 替换公式后旧的依赖关系失效
+        book.set_formula(at(1, 2), "7").unwrap();
+        assert_eq!(book.value(&at(2, 1)), ComputeResult::Number(19_f32));
+        assert_eq!(
+            book.set_value(at(1, 1), ComputeResult::Number(0_f32)),
+            vec![at(1, 3), at(2, 1)]
+        );
+        assert!(book.set_formula(at(3, 3), "1+").is_err());
+
+        // 工作表名不区分大小写
+        let lower = |row, col| CellPosition {
+            sheet: "sheet1".to_string(),
+            row,
+            col,
+        };
+        book.set_formula(at(4, 1), "sheet1!A1+SHEET1!B1").unwrap();
+        assert_eq!(book.value(&at(4, 1)), ComputeResult::Number(7_f32));
+        book.set_value(lower(1, 2), ComputeResult::Number(1_f32));
+        assert_eq!(book.value(&at(4, 1)), ComputeResult::Number(1_f32));
+        assert_eq!(book.value(&lower(2, 1)), ComputeResult::Number(2_f32));
+
+        // 大量区域引用
+        let mut book = Workbook::new();
+        for row in 1..=500 {
+            book.set_value(at(row, 1), ComputeResult::Number(1_f32));
+            book.set_formula(at(row, 2), &format!("SUM(A$1:A{})", row))
+                .unwrap();
+        }
+        let order = book.set_value(at(1, 1), ComputeResult::Number(2_f32));
+        assert_eq!(order.len(), 500);
+        assert_eq!(book.value(&at(500, 2)), ComputeResult::Number(501_f32));
+    }
+
+    #[test]
+    fn circular_reference() {
+        let mut book = Workbook::new();
+        book.set_formula(at(1, 1), "B1+1").unwrap();
+        book.set_formula(at(1, 3), "A1*2+1").unwrap();
+        let order = book.set_formula(at(1, 2), "A1+1").unwrap();
+        assert_eq!(order, vec![at(1, 1), at(1, 2), at(1, 3)]);
+        assert_eq!(book.circular_references(), &[vec![at(1, 1), at(1, 2)]]);
+        assert_eq!(book.value(&at(1, 3)), ComputeResult::Number(1_f32));
+
+        // 打破循环后不再报告
+        book.set_value(at(1, 2), ComputeResult::Number(1_f32));
+        assert!(book.circular_references().is_empty());
+        assert_eq!(book.value(&at(1, 3)), ComputeResult::Number(5_f32));
+
+        book.set_calc_settings(CalcSettings {
+            iterative: true,
+            ..CalcSettings::default()
+        });
+        book.set_formula(at(2, 1), "A2/2+1").unwrap();
+        assert_eq!(book.circular_references(), &[vec![at(2, 1)]]);
+        let value = book.value(&at(2, 1)).as_num().unwrap();
+        assert!((value - 2_f32).abs() < 0.01);
+
+        book.set_calc_settings(CalcSettings {
+            iterative: true,
+            max_iterations: 10,
+            max_change: 0.001,
+        });
+        book.set_formula(at(3, 1), "A3+1").unwrap();
+        assert_eq!(book.value(&at(3, 1)), ComputeResult::Number(10_f32));
+    }
+
+    #[test]
+    fn absolute_reference() {
+        let mut itp = Interpreter::new();
+        let cell = CellRef::parse("$B3").unwrap();
+        assert_eq!(cell.col, AxisRef::new(2, true));
+        assert_eq!(cell.row, AxisRef::new(3, false));
+        assert_eq!(cell.to_string(), "$B3");
+        for text in ["A1", "$A1", "A$1", "$A$1", "XFD1048576"] {
+            assert_eq!(CellRef::parse(text).unwrap().to_string(), text);
+        }
+        assert!(CellRef::parse("A0").is_none());
+        assert!(CellRef::parse("XFE1").is_none());
+        // 过长的列名按名称处理
+        assert!(CellRef::parse("ABCDEFGHIJKLMNOP1").is_none());
+        let grid = Grid::new(&[]);
+        for formula in ["ABCDEFGHIJKLMNOP1+1", "ABCDEFGHIJKLMNOPQ:A"] {
+            assert_eq!(
+                itp.compute(formula, position(), &grid),
+                Ok(ComputeResult::Error(ExcelError::Name))
+            );
+        }
+
+        let tokens = itp.tokenize("A$1+$A1").unwrap();
+        assert_eq!(tokens[0].0, Token::Ref(CellRef::parse("A$1").unwrap()));
+        assert_ne!(tokens[0].0, Token::Ref(CellRef::parse("A1").unwrap()));
+        assert_eq!(tokens[2].0, Token::Ref(CellRef::parse("$A1").unwrap()));
+
+        let node = itp.parse("SUM($A$1:B2,$2:3,A:$C)").unwrap();
+        let ASTNode::Call(_, args) = node.kind else {
+            panic!("expected a function call");
+        };
+        assert_eq!(
+            args[0].kind,
+            ASTNode::Range(
+                Box::new(itp.parse("$A$1").unwrap()),
+                Box::new(itp.parse("B2").unwrap())
+            )
+        );
+        assert_eq!(
+            args[1].kind,
+            ASTNode::RowRange(AxisRef::new(2, true), AxisRef::new(3, false), None)
+        );
+        assert_eq!(
+            args[2].kind,
+            ASTNode::ColRange(AxisRef::new(1, false), AxisRef::new(3, true), None)
+        );
+        assert!(itp.parse("0:3").is_err());
+    }
+
+    #[test]
+    fn relocate_formula() {
+        let mut itp = Interpreter::new();
+        let mut copy = |formula: &str, from, to| {
+            let node = itp.parse(formula).unwrap();
+            relocate(formula, &node, &from, &to)
+        };
+        // 向下填充一行，向右复制一列
+        assert_eq!(
+            copy("A1 + $A1 + A$1 + $A$1", at(2, 2), at(3, 3)),
+            "B2 + $A2 + B$1 + $A$1"
+        );
+        assert_eq!(
+            copy("sum(Sheet2!a1:B2, 1:$3, c:D)", at(1, 1), at(2, 2)),
+            "sum(Sheet2!B2:C3, 2:$3, D:E)"
+        );
+        // 超出工作表的引用
+        assert_eq!(
+            copy("A1+SUM(A1:B2)", at(2, 1), at(1, 1)),
+            "#REF!+SUM(#REF!)"
+        );
+        assert_eq!(copy("XFD1+1", at(1, 1), at(1, 2)), "#REF!+1");
+        assert_eq!(copy("SUM(A1#)", at(1, 1), at(2, 2)), "SUM(B2#)");
+        assert_eq!(copy("$A$1", at(5, 5), at(1, 1)), "$A$1");
+    }
+
+    #[test]
+    fn structural_edit() {
+        let mut itp = Interpreter::new();
+        let mut edit = |formula: &str, edit: StructuralEdit| {
+            let node = itp.parse(formula).unwrap();
+            apply_edit(formula, &node, &position(), &edit)
+        };
+        let sheet = "Sheet1".to_string();
+        assert_eq!(
+            edit(
+                "A1+A3+SUM(A2:A5)+Sheet2!A3+$A$4+SUM(3:4)+SUM(A:A)",
+                StructuralEdit::InsertRows {
+                    sheet: sheet.clone(),
+                    at: 3,
+                    count: 2
+                }
+            ),
+            "A1+A5+SUM(A2:A7)+Sheet2!A3+$A$6+SUM(5:6)+SUM(A:A)"
+        );
+        assert_eq!(
+            edit(
+                "A1+A2+A4+SUM(A1:A5)+SUM(A2:A3)+SUM(2:4)",
+                StructuralEdit::DeleteRows {
+                    sheet: sheet.clone(),
+                    at: 2,
+                    count: 2
+                }
+            ),
+            "A1+#REF!+A2+SUM(A1:A3)+SUM(#REF!)+SUM(2:2)"
+        );
+        assert_eq!(
+            edit(
+                "A1+Sheet1!B1+SUM(A:C)",
+                StructuralEdit::InsertCols {
+                    sheet: sheet.clone(),
+                    at: 2,
+                    count: 1
+                }
+            ),
+            "A1+Sheet1!C1+SUM(A:D)"
+        );
+        // 工作表名不区分大小写
+        assert_eq!(
+            edit(
+                "sheet1!B1+SHEET1!A3",
+                StructuralEdit::InsertCols {
+                    sheet: "SHEET1".to_string(),
+                    at: 2,
+                    count: 1
+                }
+            ),
+            "sheet1!C1+SHEET1!A3"
+        );
+        assert_eq!(
+            edit(
+                "B1+C1+SUM(A1:C1)",
+                StructuralEdit::DeleteCols {
+                    sheet: sheet.clone(),
+                    at: 2,
+                    count: 1
+                }
+            ),
+            "#REF!+B1+SUM(A1:B1)"
+        );
+        // 起始位置或数量为 0 时公式不变，数量过大时不会溢出
+        for (at, count) in [(0, 1), (2, 0)] {
+            let delete = StructuralEdit::DeleteRows {
+                sheet: sheet.clone(),
+                at,
+                count,
+            };
+            assert_eq!(edit("A1+SUM(A1:A5)", delete), "A1+SUM(A1:A5)");
+        }
+        assert_eq!(
+            edit(
+                "A1+SUM(A1:A5)+A:A",
+                StructuralEdit::DeleteRows {
+                    sheet: sheet.clone(),
+                    at: 2,
+                    count: usize::MAX
+                }
+            ),
+            "A1+SUM(A1:A1)+A:A"
+        );
+        assert_eq!(
+            edit(
+                "A1+B5",
+                StructuralEdit::InsertRows {
+                    sheet: sheet.clone(),
+                    at: 3,
+                    count: usize::MAX
+                }
+            ),
+            "A1+#REF!"
+        );
+        // 将 A1:B2 移动到 A3:B4
+        assert_eq!(
+            edit(
+                "A1+B2+SUM(A1:B2)+A3+C1+SUM(A1:A5)",
+                StructuralEdit::Move {
+                    range: CellRange::new(sheet, (1, 1), (2, 2)),
+                    rows: 2,
+                    cols: 0
+                }
+            ),
+            "A3+B4+SUM(A3:B4)+#REF!+C1+SUM(A1:A5)"
+        );
+        assert_eq!(
+            edit(
+                "sheet1!A1+B1",
+                StructuralEdit::Move {
+                    range: CellRange::new("SHEET1".to_string(), (1, 1), (1, 2)),
+                    rows: 1,
+                    cols: 0
+                }
+            ),
+            "sheet1!A2+B2"
+        );
+    }
+
+    #[test]
+    fn rename_and_delete_sheet() {
+        let mut itp = Interpreter::new();
+        let formula = "Sheet1!A1+'Sheet1'!B2:C3+SUM(sheet1!1:2,Other!A:B)+A1";
+        let node = itp.parse(formula).unwrap();
+        assert_eq!(
+            rename_sheet(formula, &node, "Sheet1", "My Sheet"),
+            "'My Sheet'!A1+'My Sheet'!B2:C3+SUM('My Sheet'!1:2,Other!A:B)+A1"
+        );
+        assert_eq!(
+            rename_sheet(formula, &node, "Other", "Data"),
+            "Sheet1!A1+'Sheet1'!B2:C3+SUM(sheet1!1:2,Data!A:B)+A1"
+        );
+        assert_eq!(
+            rename_sheet(formula, &node, "Other", "Q1's"),
+            "Sheet1!A1+'Sheet1'!B2:C3+SUM(sheet1!1:2,'Q1''s'!A:B)+A1"
+        );
+        assert_eq!(
+            delete_sheet(formula, &node, "Sheet1", &[]),
+            "#REF!+#REF!+SUM(#REF!,Other!A:B)+A1"
+        );
+        for name in ["A1", "TRUE", "2024", "Q1 Sales", "", "a.b"] {
+            assert!(quote_sheet_name(name).starts_with('\''));
+        }
+    }
+
+    #[test]
+    fn print_formula() {
+        let mut itp = Interpreter::new();
+        let mut print = |formula: &str| to_formula(&itp.parse(formula).unwrap());
+        assert_eq!(print("(1+2)*3"), "(1+2)*3");
+        assert_eq!(print("1+(2*3)"), "1+2*3");
+        assert_eq!(print("(1-2)-3"), "1-2-3");
+        assert_eq!(print("1-(2-3)"), "1-(2-3)");
+        assert_eq!(print("(2^3)^2"), "2^3^2");
+        assert_eq!(print("2^(3^2)"), "2^(3^2)");
+        assert_eq!(print("-(1+2)"), "-(1+2)");
+        assert_eq!(print("(-2)^2"), "-2^2");
+        assert_eq!(print("-(-A1)"), "--A1");
+        assert_eq!(print("(1=2)&\"x\""), "(1=2)&\"x\"");
+        assert_eq!(print(" SUM( a1 , 2 ) "), "SUM(A1,2)");
+        assert_eq!(
+            print("'Sheet1'!a1:'My Sheet'!$B$2"),
+            "Sheet1!A1:'My Sheet'!$B$2"
+        );
+        assert_eq!(print("50%"), "0.5");
+        assert_eq!(print("IF( A1 , , 1 )"), "IF(A1,,1)");
+        assert_eq!(print("(Data) (B1,C1)"), "(Data) (B1,C1)");
+        assert_eq!(print("(A1 Data) (B1,C1)"), "(A1 Data) (B1,C1)");
+        assert_eq!(print("(Jan):Mar!B5"), "(Jan):Mar!B5");
+        assert_eq!(print("A1 (1)"), "A1 (1)");
+
+        let formulas = [
+            "1+2*3-4/5^6",
+            "-1+-2*+3",
+            "2^-1",
+            "1--1",
+            "(1+2)*(3-4)",
+            "1<>2=TRUE",
+            "\"a\"&\"b\"&(1<2)",
+            "IF(A1>0,SUM($A$1:B2,3:$5,A:$C),#N/A)",
+            "-SUM(1,2)",
+            "-A1:B2",
+            "Sheet2!A1+'Q1 Sales'!2:3+Data!Total",
+            "IFERROR(1/0,\"div\")",
+            "1E+20+0.001",
+            "\"say \"\"hi\"\"\"&\"\"\"\"",
+            "'Q1 ''24'!A1+'it''s'!B:C",
+            "SUM({1,-2.5;\"a\",#N/A}*-{+3;TRUE})",
+            "SUM(Sheet1!A1#)+$B$2#",
+            "@A1:A3+-@Data!B:B*@A1#",
+            "SUM((A1:A3,C1:C3),A1:C5 B2:D4)-(1:2 B:B)",
+            "SUM(Sheet1!A1:Sheet1!B2:Top,IF(TRUE,A1:A2,B1):(B2 C:C))",
+            "SUM(Jan:Dec!B5,'Q1:Q 4'!A1:B2)+COUNT(Jan:Mar!C:C)",
+            "(Data) (B1,C1)",
+            "IF(A1,,1)+SUM(,A1,)+NOW()",
+            "A1 (1)+(1) A1+(\"a\") (TRUE)+(#REF!) ({1,2})",
+            "--A1+-+1^2",
+            "SUM((Jan):Mar!B5,(A1:Jan):'Q 1'!C1#)",
+            "SUM((A1 Data) (B1,C1),Data A:A)",
+        ];
+        for formula in formulas {
+            let ast = itp.parse(formula).unwrap();
+            let printed = to_formula(&ast);
+            assert_eq!(itp.parse(&printed).unwrap(), ast, "{}", printed);
+        }
+    }
+
+    #[test]
+    fn format_formula_options() {
+        let mut itp = Interpreter::new();
+        let formula = "if(a1>0,sum(a1:b2)*2^2,iferror(1/0,\"x\"))";
+        let ast = itp.parse(formula).unwrap();
+        assert_eq!(
+            format_formula(&ast, &FormatOptions::default()),
+            "IF(A1 > 0, SUM(A1:B2) * 2^2, IFERROR(1 / 0, \"x\"))"
+        );
+        let ast = itp.parse("sum(index(a:a,1):b10)").unwrap();
+        assert_eq!(
+            format_formula(&ast, &FormatOptions::default()),
+            "SUM(INDEX(A:A, 1):B10)"
+        );
+        let ast = itp.parse("sum((Jan):Mar!b5)").unwrap();
+        assert_eq!(
+            format_formula(&ast, &FormatOptions::default()),
+            "SUM((Jan):Mar!B5)"
+        );
+        let ast = itp.parse("(Data) (b1,c1)").unwrap();
+        assert_eq!(
+            format_formula(&ast, &FormatOptions::default()),
+            "(Data) (B1, C1)"
+        );
+        let ast = itp.parse(formula).unwrap();
+        let options = FormatOptions {
+            width: 24,
+            indent: 2,
+            uppercase: false,
+        };
+        let formatted = format_formula(&ast, &options);
+        assert_eq!(
+            formatted,
+            "if(\n  A1 > 0,\n  sum(A1:B2) * 2^2,\n  iferror(1 / 0, \"x\")\n)"
+        );
+        assert_eq!(itp.parse(&formatted).unwrap(), ast);
+
+        let options = FormatOptions {
+            width: 10,
+            ..FormatOptions::default()
+        };
+        let formatted = format_formula(&ast, &options);
+        assert_eq!(
+            formatted,
+            "IF(\n    A1 > 0,\n    SUM(\n        A1:B2\n    ) * 2^2,\n    IFERROR(\n        1 / 0,\n        \"x\"\n    )\n)"
+        );
+        let grid = Grid(HashMap::new());
+        assert_eq!(
+            itp.compute(&formatted, position(), &grid),
+            itp.compute(formula, position(), &grid)
+        );
+    }
+
+    #[test]
+    fn quote_escapes() {
+        let mut itp = Interpreter::new();
+        let tokens = itp.tokenize("\"say \"\"hi\"\"\"&'Q1 ''24'!A1").unwrap();
+        assert_eq!(tokens[0].0, Token::String("say \"hi\"".to_string()));
+        assert_eq!(tokens[0].1, Span::new(0, 12));
+        assert_eq!(tokens[2].0, Token::Sheet("Q1 '24".to_string()));
+
+        let mut grid = Grid(HashMap::new());
+        grid.0.insert(
+            ("Q1 '24".to_string(), 1, 1),
+            ComputeResult::String("x".to_string()),
+        );
+        assert_eq!(
+            itp.compute("\"say \"\"hi\"\"\"&'Q1 ''24'!A1", position(), &grid),
+            Ok(ComputeResult::String("say \"hi\"x".to_string()))
+        );
+        assert_eq!(
+            itp.compute("\"\"\"\"", position(), &grid),
+            Ok(ComputeResult::String("\"".to_string()))
+        );
+        assert_eq!(
+            itp.compute("\"a\"\"", position(), &grid).unwrap_err().kind,
+            ErrorKind::UnterminatedString
+        );
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        let mut itp = Interpreter::new();
+        let tokens = itp
+            .tokenize("数据!A1+Überblick!b2+_tax.rate*\\rate+STDEV.S(1)+A1.B+é12")
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Sheet("数据".to_string()),
+                Token::Csref,
+                Token::Ref(CellRef::parse("A1").unwrap()),
+                Token::Plus,
+                Token::Sheet("Überblick".to_string()),
+                Token::Csref,
+                Token::Ref(CellRef::parse("B2").unwrap()),
+                Token::Plus,
+                Token::Var("_tax.rate".to_string()),
+                Token::Mul,
+                Token::Var("\\rate".to_string()),
+                Token::Plus,
+                Token::Var("STDEV.S".to_string()),
+                Token::Lparen,
+                Token::Number(1_f32),
+                Token::Rparen,
+                Token::Plus,
+                Token::Var("A1.B".to_string()),
+                Token::Plus,
+                Token::Var("é12".to_string()),
+            ]
+        );
+
+        let mut grid = Grid(HashMap::new());
+        grid.0
+            .insert(("数据".to_string(), 1, 1), ComputeResult::Number(2_f32));
+        assert_eq!(
+            itp.compute("数据!A1*2", position(), &grid),
+            Ok(ComputeResult::Number(4_f32))
+        );
+        assert_eq!(quote_sheet_name("数据"), "数据");
+        assert_eq!(quote_sheet_name("Überblick"), "Überblick");
+        let ast = itp.parse("SUM('数据'!A1:B2,Überblick!C:C)").unwrap();
+        assert_eq!(to_formula(&ast), "SUM(数据!A1:B2,Überblick!C:C)");
+    }
+
+    #[test]
+    fn defined_names() {
+        let mut itp = Interpreter::new();
+        let mut grid = Grid(HashMap::new());
+        for row in 1..=3 {
+            grid.0.insert(
+                ("Sheet1".to_string(), row, 1),
+                ComputeResult::Number(row as f32),
+            );
+        }
+        let sheet2 = CellPosition {
+            sheet: "Sheet2".to_string(),
+            row: 1,
+            col: 1,
+        };
+        itp.define_name("Rate", None, "0.5").unwrap();
+        itp.define_name("Rate", Some("Sheet2"), "2").unwrap();
+        itp.define_name("Data", None, "Sheet1!$A$1:$A$3").unwrap();
+        itp.define_name("Total", None, "SUM(Data)").unwrap();
+        itp.define_name("Loop", None, "Loop+1").unwrap();
+
+        assert_eq!(
+            itp.compute("Total*rate", position(), &grid),
+            Ok(ComputeResult::Number(3_f32))
+        );
+        // 工作表级别的名称优先
+        assert_eq!(
+            itp.compute("Total*Rate", sheet2.clone(), &grid),
+            Ok(ComputeResult::Number(12_f32))
+        );
+        assert_eq!(
+            itp.compute("Sheet2!Rate", position(), &grid),
+            Ok(ComputeResult::Number(2_f32))
+        );
+        assert_eq!(
+            itp.compute("MAX(Data)+Unknown", position(), &grid),
+            Ok(ComputeResult::Error(ExcelError::Name))
+        );
+        assert_eq!(
+            itp.compute("Sheet1!Rate", position(), &grid),
+            Ok(ComputeResult::Error(ExcelError::Name))
+        );
+        // 名称的作用范围不区分大小写
+        assert_eq!(
+            itp.compute("SHEET2!Rate", position(), &grid),
+            Ok(ComputeResult::Number(2_f32))
+        );
+        let lower = CellPosition {
+            sheet: "sheet2".to_string(),
+            ..sheet2.clone()
+        };
+        assert_eq!(
+            itp.compute("Rate", lower, &grid),
+            Ok(ComputeResult::Number(2_f32))
+        );
+        assert_eq!(
+            itp.compute("Loop", position(), &grid),
+            Ok(ComputeResult::Error(ExcelError::Name))
+        );
+
+        let mut book = Workbook::new();
+        let a1 = position();
+        let b1 = CellPosition {
+            sheet: "Sheet1".to_string(),
+            row: 1,
+            col: 2,
+        };
+        book.set_value(a1.clone(), ComputeResult::Number(4_f32));
+        book.set_formula(b1.clone(), "Price*2").unwrap();
+        assert_eq!(book.value(&b1), ComputeResult::Error(ExcelError::Name));
+        book.define_name("Price", None, "Sheet1!A1").unwrap();
+        assert_eq!(book.value(&b1), ComputeResult::Number(8_f32));
+        // 通过名称引用的单元格变化后同样会重算
+        assert_eq!(
+            book.set_value(a1, ComputeResult::Number(5_f32)),
+            vec![b1.clone()]
+        );
+        assert_eq!(book.value(&b1), ComputeResult::Number(10_f32));
+        book.remove_name("Price", None);
+        assert_eq!(book.value(&b1), ComputeResult::Error(ExcelError::Name));
+
+        book.set_formula(at(2, 1), "Rate").unwrap();
+        book.define_name("Rate", Some("sheet1"), "3").unwrap();
+        assert_eq!(book.value(&at(2, 1)), ComputeResult::Number(3_f32));
+        book.remove_name("Rate", Some("SHEET1"));
+        assert_eq!(
+            book.value(&at(2, 1)),
+            ComputeResult::Error(ExcelError::Name)
+        );
+    }
+
+    #[test]
+    fn array_constants() {
+        let mut itp = Interpreter::new();
+        let grid = Grid(HashMap::new());
+        let mut compute = |formula: &str| itp.compute(formula, position(), &grid);
+        let num = ComputeResult::Number;
+        assert_eq!(compute("SUM({1,2,3}*2)"), Ok(num(12_f32)));
+        assert_eq!(
+            compute("{1,2;3,4}+{10;20}"),
+            Ok(ComputeResult::Array(vec![
+                vec![num(11_f32), num(12_f32)],
+                vec![num(23_f32), num(24_f32)]
+            ]))
+        );
+        assert_eq!(
+            compute("-{1,2,3}+{1,2}"),
+            Ok(ComputeResult::Array(vec![vec![
+                num(0_f32),
+                num(0_f32),
+                ComputeResult::Error(ExcelError::NA)
+            ]]))
+        );
+        assert_eq!(
+            compute("{1,\"a\",TRUE,#DIV/0!}&\"!\""),
+            Ok(ComputeResult::Array(vec![vec![
+                ComputeResult::String("1!".to_string()),
+                ComputeResult::String("a!".to_string()),
+                ComputeResult::String("TRUE!".to_string()),
+                ComputeResult::Error(ExcelError::Div0)
+            ]]))
+        );
+        assert_eq!(
+            compute("{1,-2}=-2"),
+            Ok(ComputeResult::Array(vec![vec![
+                ComputeResult::Bool(false),
+                ComputeResult::Bool(true)
+            ]]))
+        );
+        // 聚合函数忽略数组中的文本和逻辑值
+        assert_eq!(compute("SUM({1,\"a\",TRUE;4,5,6})"), Ok(num(16_f32)));
+        assert_eq!(compute("COUNT({1,\"a\",TRUE})"), Ok(num(1_f32)));
+        assert_eq!(
+            compute("MAX({1,#N/A})"),
+            Ok(ComputeResult::Error(ExcelError::NA))
+        );
+        assert_eq!(compute("{5,6}+1*1"), compute("{6,7}"));
+        // 标量参数为数组时按元素分别求值
+        assert_eq!(
+            compute("IF({FALSE,TRUE},1,2)"),
+            Ok(ComputeResult::Array(vec![vec![num(2_f32), num(1_f32)]]))
+        );
+        assert_eq!(
+            compute("IF({TRUE,FALSE},{1,2},{3,4})"),
+            Ok(ComputeResult::Array(vec![vec![num(1_f32), num(4_f32)]]))
+        );
+
+        for formula in ["{1,2;3}", "{1,A1}", "{1+2}", "{}", "{-\"a\"}"] {
+            assert_eq!(
+                itp.parse(formula).unwrap_err().kind,
+                ErrorKind::InvalidArray,
+                "{}",
+                formula
+            );
+        }
+        assert_eq!(
+            itp.parse("{1,2").unwrap_err().kind,
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn dynamic_arrays() {
+        let num = ComputeResult::Number;
+        let mut book = Workbook::new();
+        book.set_formula(at(1, 1), "{1,2;3,4}").unwrap();
+        book.set_formula(at(5, 1), "SUM(A1#)").unwrap();
+        book.set_formula(at(5, 2), "B2*10").unwrap();
+        assert_eq!(
+            book.spill_range("Sheet1", 1, 1),
+            Some(CellRange::new("Sheet1".to_string(), (1, 1), (2, 2)))
+        );
+        assert_eq!(book.value(&at(1, 1)), num(1_f32));
+        assert_eq!(book.value(&at(2, 2)), num(4_f32));
+        assert_eq!(book.value(&at(5, 1)), num(10_f32));
+        assert_eq!(book.value(&at(5, 2)), num(40_f32));
+
+        // 溢出区域被阻挡
+        book.set_value(at(1, 2), ComputeResult::String("x".to_string()));
+        assert_eq!(
+            book.value(&at(1, 1)),
+            ComputeResult::Error(ExcelError::Spill)
+        );
+        assert_eq!(book.value(&at(2, 2)), ComputeResult::Empty);
+        assert_eq!(book.value(&at(5, 1)), ComputeResult::Error(ExcelError::Ref));
+        assert_eq!(book.value(&at(5, 2)), num(0_f32));
+        book.clear(at(1, 2));
+        assert_eq!(book.value(&at(1, 2)), num(2_f32));
+        assert_eq!(book.value(&at(5, 1)), num(10_f32));
+        assert_eq!(book.value(&at(5, 2)), num(40_f32));
+
+        // 数组大小变化后重新溢出
+        book.set_formula(at(1, 1), "{1;2;3}").unwrap();
+        assert_eq!(book.value(&at(1, 2)), ComputeResult::Empty);
+        assert_eq!(book.value(&at(3, 1)), num(3_f32));
+        assert_eq!(book.value(&at(5, 1)), num(6_f32));
+        assert_eq!(book.value(&at(5, 2)), num(0_f32));
+
+        // 区域引用的结果同样溢出
+        book.set_formula(at(1, 4), "A1:A3*2").unwrap();
+        assert_eq!(book.value(&at(3, 4)), num(6_f32));
+        book.set_formula(at(1, 5), "A2:A2").unwrap();
+        assert_eq!(book.value(&at(1, 5)), num(2_f32));
+        // 溢出到其他公式所在的单元格或超出工作表
+        assert_eq!(book.set_formula(at(1, 3), "{1,2}").unwrap(), vec![at(1, 3)]);
+        assert_eq!(
+            book.value(&at(1, 3)),
+            ComputeResult::Error(ExcelError::Spill)
+        );
+        book.set_formula(at(1, 16384), "{1,2}").unwrap();
+        assert_eq!(
+            book.value(&at(1, 16384)),
+            ComputeResult::Error(ExcelError::Spill)
+        );
+
+        // 明确的区域保持原有大小，空单元格显示为 0
+        let mut book = Workbook::new();
+        for row in [1, 2, 4, 5] {
+            book.set_value(at(row, 1), num(row as f32));
+        }
+        book.set_formula(at(1, 3), "Sheet1!A1:A10*2").unwrap();
+        assert_eq!(
+            book.spill_range("Sheet1", 1, 3),
+            Some(CellRange::new("Sheet1".to_string(), (1, 3), (10, 3)))
+        );
+        assert_eq!(book.value(&at(10, 3)), num(0_f32));
+        book.set_formula(at(1, 4), "{1;2;3;4;5;6}+Sheet1!A1:A6")
+            .unwrap();
+        assert_eq!(book.value(&at(6, 4)), num(6_f32));
+        book.set_formula(at(1, 5), "A1:A3").unwrap();
+        assert_eq!(book.value(&at(3, 5)), num(0_f32));
+        book.set_formula(at(1, 6), "A3").unwrap();
+        assert_eq!(book.value(&at(1, 6)), num(0_f32));
+
+        // 写入空数组
+        book.set_formula(at(8, 2), "A8+1").unwrap();
+        for array in [vec![], vec![vec![]]] {
+            book.set_value(at(8, 1), ComputeResult::Array(array));
+            assert_eq!(book.value(&at(8, 1)), ComputeResult::Empty);
+            assert_eq!(book.value(&at(8, 2)), num(1_f32));
+        }
+    }
+
+    #[test]
+    fn implicit_intersection() {
+        let num = ComputeResult::Number;
+        let mut cells = HashMap::new();
+        for row in 1..=3 {
+            cells.insert(("Sheet1".to_string(), row, 1), num(row as f32));
+            cells.insert(("Sheet1".to_string(), row, 2), num(row as f32 * 10_f32));
+        }
+        let grid = Grid(cells);
+        let mut itp = Interpreter::new();
+
+        // @ 取与公式所在行或列相交的单元格
+        assert_eq!(itp.compute("@A1:A3", at(2, 5), &grid), Ok(num(2_f32)));
+        assert_eq!(itp.compute("@A1:C1*2", at(9, 2), &grid), Ok(num(20_f32)));
+        assert_eq!(itp.compute("-@B:B", at(3, 9), &grid), Ok(num(-30_f32)));
+        assert_eq!(itp.compute("@A1:B3", at(3, 2), &grid), Ok(num(30_f32)));
+        assert_eq!(
+            itp.compute("@A1:A3", at(5, 5), &grid),
+            Ok(ComputeResult::Error(ExcelError::Value))
+        );
+        assert_eq!(
+            itp.compute("@A1:B3", at(2, 5), &grid),
+            Ok(ComputeResult::Error(ExcelError::Value))
+        );
+        assert_eq!(itp.compute("@{7,8;9,0}", at(1, 1), &grid), Ok(num(7_f32)));
+        // 动态数组方式下区域逐个单元格参与运算
+        assert_eq!(
+            itp.compute("A1:A3+1", at(2, 5), &grid),
+            Ok(ComputeResult::Array(vec![
+                vec![num(2_f32)],
+                vec![num(3_f32)],
+                vec![num(4_f32)]
+            ]))
+        );
+
+        itp.set_legacy_arrays(true);
+        assert_eq!(itp.compute("A1:A3+1", at(2, 5), &grid), Ok(num(3_f32)));
+        assert_eq!(itp.compute("-B1:B3", at(3, 5), &grid), Ok(num(-30_f32)));
+        assert_eq!(
+            itp.compute("NOT(A1:A3)", at(1, 5), &grid),
+            Ok(ComputeResult::Bool(false))
+        );
+        assert_eq!(itp.compute("SUM(A1:A3)", at(1, 5), &grid), Ok(num(6_f32)));
+
+        // 旧版工作簿中公式的结果不溢出
+        let mut book = Workbook::new();
+        for row in 1..=3 {
+            book.set_value(at(row, 1), num(row as f32));
+        }
+        book.set_formula(at(2, 3), "A1:A3*2").unwrap();
+        book.set_formula(at(1, 4), "{5,6}").unwrap();
+        assert_eq!(book.value(&at(4, 3)), num(6_f32));
+        assert_eq!(book.value(&at(3, 3)), num(4_f32));
+        book.interpreter_mut().set_legacy_arrays(true);
+        book.recalculate_all();
+        assert_eq!(book.value(&at(2, 3)), num(4_f32));
+        assert_eq!(book.value(&at(3, 3)), ComputeResult::Empty);
+        assert_eq!(book.value(&at(1, 4)), num(5_f32));
+        assert_eq!(book.value(&at(1, 5)), ComputeResult::Empty);
+    }
+
+    #[test]
+    fn reference_operators() {
+        let mut cells = HashMap::new();
+        for row in 1..=5 {
+            for col in 1..=4 {
+                let value = ComputeResult::Number((row * 10 + col) as f32);
+                cells.insert(("Sheet1".to_string(), row, col), value);
+            }
+        }
+        let grid = Grid(cells);
+        let mut itp = Interpreter::new();
+        let mut compute = |formula: &str| itp.compute(formula, position(), &grid);
+        let num = ComputeResult::Number;
+        let range = |from, to| {
+            Ok(ComputeResult::Range(CellRange::new(
+                "Sheet1".to_string(),
+                from,
+                to,
+            )))
+        };
+
+        assert_eq!(compute("A1:C5 B2:D4"), range((2, 2), (4, 3)));
+        assert_eq!(compute("SUM(A1:C5 B2:D4)"), Ok(num(195_f32)));
+        assert_eq!(compute("B:B 3:3"), range((3, 2), (3, 2)));
+        assert_eq!(compute("1:2 2:3"), range((2, 1), (2, 16384)));
+        assert_eq!(compute("SUM(A1:A3 A2:C2) * 2"), Ok(num(42_f32)));
+        assert_eq!(
+            compute("A1:A3 C1:C3"),
+            Ok(ComputeResult::Error(ExcelError::Null))
+        );
+        assert_eq!(
+            compute("A1 (1)"),
+            Ok(ComputeResult::Error(ExcelError::Value))
+        );
+        // 工作表名不区分大小写
+        assert_eq!(compute("SUM(Sheet1!A1:C3 sheet1!B2)"), Ok(num(22_f32)));
+
+        assert_eq!(compute("SUM((A1:A2,C1,D5))"), Ok(num(99_f32)));
+        assert_eq!(compute("COUNT((A1:B2,B2:C3))"), Ok(num(8_f32)));
+        assert_eq!(
+            compute("SUM((A1:A5,C1:C5) B2:D3)"),
+            Ok(num(23_f32 + 33_f32))
+        );
+        assert_eq!(
+            compute("(A1,B1)+1"),
+            Ok(ComputeResult::Error(ExcelError::Value))
+        );
+        assert_eq!(
+            compute("SUM((A1,#REF!))"),
+            Ok(ComputeResult::Error(ExcelError::Ref))
+        );
+
+        // 函数名与括号之间、运算符两侧的空格不是交集
+        assert_eq!(compute("SUM (A1, B1) + A1"), Ok(num(34_f32)));
+        let tokens = itp.tokenize("A1 (B1) C:C").unwrap();
+        assert_eq!(
+            tokens
+                .iter()
+                .filter(|(t, _)| *t == Token::Intersect)
+                .count(),
+            2
+        );
+
+        // 格式化后仍能解析为相同的语法树
+        let ast = itp.parse("SUM((a1:a3,c1:c3),a1:c5 b2:d4)").unwrap();
+        let formatted = format_formula(&ast, &FormatOptions::default());
+        assert_eq!(formatted, "SUM((A1:A3, C1:C3), A1:C5 B2:D4)");
+        assert_eq!(itp.parse(&formatted).unwrap(), ast);
+
+        let mut book = Workbook::new();
+        book.set_value(at(2, 2), num(5_f32));
+        book.set_formula(at(1, 1), "SUM(sheet1!B1:C3 Sheet1!B2)")
+            .unwrap();
+        assert_eq!(book.value(&at(1, 1)), num(5_f32));
+    }
+
+    #[test]
+    fn general_range_operator() {
+        let num = ComputeResult::Number;
+        let mut book = Workbook::new();
+        for row in 1..=4 {
+            for col in 1..=3 {
+                book.set_value(at(row, col), num((row * 10 + col) as f32));
+            }
+        }
+        book.define_name("Top", None, "Sheet1!$B$1").unwrap();
+        book.define_name("Bottom", None, "Sheet1!$B$3").unwrap();
+
+        // 区域为两侧引用的外接矩形
+        book.set_formula(at(9, 1), "SUM(Top:Bottom)").unwrap();
+        assert_eq!(book.value(&at(9, 1)), num(12_f32 + 22_f32 + 32_f32));
+        book.set_formula(at(9, 2), "SUM(Sheet1!A1:Sheet1!B2)")
+            .unwrap();
+        assert_eq!(book.value(&at(9, 2)), num(66_f32));
+        book.set_formula(at(9, 3), "SUM(A1:B2:C1)").unwrap();
+        assert_eq!(book.value(&at(9, 3)), num(102_f32));
+        book.set_formula(at(10, 1), "SUM(IF(A1>0,A3:B3,A1):(B1:C2 C2:C4))")
+            .unwrap();
+        assert_eq!(book.value(&at(10, 1)), num(162_f32));
+        book.set_formula(at(10, 2), "Top:A1").unwrap();
+        assert_eq!(book.value(&at(10, 2)), num(11_f32));
+        assert_eq!(book.value(&at(10, 3)), num(12_f32));
+        book.set_formula(at(11, 1), "SUM(Sheet1!A1:Sheet2!B2)")
+            .unwrap();
+        assert_eq!(
+            book.value(&at(11, 1)),
+            ComputeResult::Error(ExcelError::Value)
+        );
+        book.set_formula(at(11, 2), "SUM(A1:#REF!)").unwrap();
+        assert_eq!(
+            book.value(&at(11, 2)),
+            ComputeResult::Error(ExcelError::Ref)
+        );
+
+        // 外接矩形中间的单元格变化时同样重算
+        book.set_value(at(2, 2), num(0_f32));
+        assert_eq!(book.value(&at(9, 1)), num(44_f32));
+        // 两端的工作表名大小写不同
+        book.set_formula(at(12, 1), "SUM(Sheet1!A1:sheet1!B2)")
+            .unwrap();
+        book.set_formula(at(12, 2), "SUM(A1:B2)").unwrap();
+        assert_eq!(book.value(&at(12, 1)), book.value(&at(12, 2)));
+        assert!(matches!(book.value(&at(12, 1)), ComputeResult::Number(_)));
+
+        let mut itp = Interpreter::new();
+        for formula in ["A1:1", "A1:\"B2\"", "1:A1", "A1:{1}"] {
+            assert_eq!(
+                itp.parse(formula).unwrap_err().kind,
+                ErrorKind::InvalidRange,
+                "{}",
+                formula
+            );
+        }
+        let node = itp.parse("Top:B2").unwrap();
+        assert_eq!(relocate("Top:B2", &node, &at(1, 1), &at(2, 1)), "Top:B3");
+    }
+
+    #[test]
+    fn three_d_references() {
+        let at = |sheet: &str, row, col| CellPosition {
+            sheet: sheet.to_string(),
+            row,
+            col,
+        };
+        let num = ComputeResult::Number;
+        let mut book = Workbook::new();
+        book.add_sheet("Summary");
+        for (i, sheet) in ["Jan", "Feb", "Mar"].iter().enumerate() {
+            book.set_value(at(sheet, 5, 2), num((i + 1) as f32));
+            book.set_value(at(sheet, 1, 1), num(10_f32));
+        }
+        book.set_value(at("Other", 5, 2), num(100_f32));
+        assert_eq!(
+            book.sheet_names(),
+            ["Summary", "Jan", "Feb", "Mar", "Other"]
+        );
+
+        book.set_formula(at("Summary", 1, 1), "SUM(Jan:Mar!B5)")
+            .unwrap();
+        book.set_formula(at("Summary", 1, 2), "SUM(Mar:Feb!A1:B5)")
+            .unwrap();
+        book.set_formula(at("Summary", 1, 3), "COUNT('Jan:Feb'!B:B)")
+            .unwrap();
+        book.set_formula(at("Summary", 1, 4), "SUM(Jan:Apr!B5)")
+            .unwrap();
+        book.set_formula(at("Summary", 1, 5), "Jan:Mar!B5").unwrap();
+        book.set_formula(at("Summary", 1, 6), "SUM(Jan:Jan!B5)")
+            .unwrap();
+        assert_eq!(book.value(&at("Summary", 1, 1)), num(6_f32));
+        assert_eq!(book.value(&at("Summary", 1, 2)), num(25_f32));
+        assert_eq!(book.value(&at("Summary", 1, 3)), num(2_f32));
+        assert_eq!(
+            book.value(&at("Summary", 1, 4)),
+            ComputeResult::Error(ExcelError::Ref)
+        );
+        assert_eq!(
+            book.value(&at("Summary", 1, 5)),
+            ComputeResult::Error(ExcelError::Value)
+        );
+        assert_eq!(book.value(&at("Summary", 1, 6)), num(1_f32));
+
+        // 修改其中任意工作表的单元格都会重算
+        let order = book.set_value(at("Feb", 5, 2), num(20_f32));
+        assert!(order.contains(&at("Summary", 1, 1)));
+        assert_eq!(book.value(&at("Summary", 1, 1)), num(24_f32));
+        // 三维引用一端的工作表出现后引用生效
+        book.set_value(at("Apr", 5, 2), num(1000_f32));
+        assert_eq!(book.value(&at("Summary", 1, 4)), num(1124_f32));
+
+        let mut itp = Interpreter::new();
+        let formula = "SUM(Jan:Mar!B5, 'Q 1:Q4'!A1)";
+        let node = itp.parse(formula).unwrap();
+        assert_eq!(node, itp.parse("SUM('Jan:Mar'!B5, 'Q 1:Q4'!A1)").unwrap());
+        assert_eq!(
+            rename_sheet(formula, &node, "mar", "Q1"),
+            "SUM('Jan:Q1'!B5, 'Q 1:Q4'!A1)"
+        );
+        let sheets = ["Jan", "Feb", "Mar", "Q 1", "Q4"].map(String::from);
+        assert_eq!(
+            delete_sheet(formula, &node, "Q4", &sheets),
+            "SUM(Jan:Mar!B5, 'Q 1:Q 1'!A1)"
+        );
+        // 删除一端的工作表时该端向内移动，范围内的工作表都被删除时为 #REF!
+        assert_eq!(
+            delete_sheet(formula, &node, "jan", &sheets),
+            "SUM(Feb:Mar!B5, 'Q 1:Q4'!A1)"
+        );
+        let node = itp.parse("SUM(Mar:Jan!B5,Q4:Q4!A1)").unwrap();
+        assert_eq!(
+            delete_sheet("SUM(Mar:Jan!B5,Q4:Q4!A1)", &node, "Mar", &sheets),
+            "SUM(Feb:Jan!B5,Q4:Q4!A1)"
+        );
+        assert_eq!(
+            delete_sheet("SUM(Mar:Jan!B5,Q4:Q4!A1)", &node, "Q4", &sheets),
+            "SUM(Mar:Jan!B5,#REF!)"
+        );
+        let node = itp.parse(formula).unwrap();
+        assert_eq!(
+            relocate(formula, &node, &at("S", 1, 1), &at("S", 2, 2)),
+            "SUM(Jan:Mar!C6, 'Q 1:Q4'!B2)"
+        );
+        let insert = StructuralEdit::InsertRows {
+            sheet: "Jan".to_string(),
+            at: 1,
+            count: 1,
+        };
+        assert_eq!(
+            apply_edit(formula, &node, &at("Jan", 9, 9), &insert),
+            formula
+        );
+        for formula in ["Jan:Mar!Total", "Jan:Mar!A1#", "Jan:Mar!", "'Jan:'!A1"] {
+            assert!(itp.parse(formula).is_err(), "{}", formula);
+        }
+        // 区域运算符左侧是带工作表的单元格时不是三维引用
+        assert!(matches!(
+            itp.parse("Jan!A1:Mar!B2").unwrap().kind,
+            ASTNode::Range(..)
+        ));
+    }
 }
@@ -1,13 +1,15 @@
-mod interpreter;
-mod lexer;
-mod node;
-mod parser;
-mod token;
-mod utils;
-
-use crate::interpreter::{CellPosition, Interpreter};
 use pyroscope::PyroscopeAgent;
 use std::time::Instant;
+use web_excel_grammer_rust::{CellPosition, ComputeResult, DataProvider, Interpreter};
+
+struct NoData;
+
+impl DataProvider for NoData {
+    fn cell(&self, _sheet: &str, _row: usize, _col: usize) -> Option<ComputeResult> {
+        None
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut agent = PyroscopeAgent::builder("http://localhost:4040", "rust-app")
         .sample_rate(100)
@@ -28,7 +30,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             row: 1,
             col: 1,
         };
-        let _ = itp.compute(input, position);
+        let _ = itp.compute(input, position, &NoData);
         // println!("{:?}", v);
 
         // if let Ok(_) = v {
@@ -43,4 +45,3 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     agent.stop();
     Ok(())
 }
-
@@ -0,0 +1,48 @@
+use crate::node::Node;
+use std::collections::HashMap;
+
+/// 名称管理器。名称的定义保存为语法树，可以是引用（如 Sheet1!$A$1:$B$10）、常量或公式。
+/// 名称和工作表名都不区分大小写；工作表级别的名称只在该工作表中可见，并优先于同名的工作簿级别名称
+#[derive(Default)]
+pub struct NameManager {
+    workbook: HashMap<String, Node>,
+    /// 键为 (小写的工作表名, 名称)
+    sheets: HashMap<(String, String), Node>,
+}
+
+impl NameManager {
+    pub fn new() -> NameManager {
+        NameManager::default()
+    }
+
+    /// 定义名称，scope 为 None 时定义工作簿级别的名称，已存在时覆盖原有定义
+    pub fn define(&mut self, name: &str, scope: Option<&str>, definition: Node) {
+        let name = name.to_uppercase();
+        match scope {
+            Some(sheet) => self.sheets.insert((sheet.to_lowercase(), name), definition),
+            None => self.workbook.insert(name, definition),
+        };
+    }
+
+    pub fn remove(&mut self, name: &str, scope: Option<&str>) -> Option<Node> {
+        let name = name.to_uppercase();
+        match scope {
+            Some(sheet) => self.sheets.remove(&(sheet.to_lowercase(), name)),
+            None => self.workbook.remove(&name),
+        }
+    }
+
+    /// 查找在指定范围内定义的名称
+    pub fn get(&self, name: &str, scope: Option<&str>) -> Option<&Node> {
+        let name = name.to_uppercase();
+        match scope {
+            Some(sheet) => self.sheets.get(&(sheet.to_lowercase(), name)),
+            None => self.workbook.get(&name),
+        }
+    }
+
+    /// 查找在工作表 sheet 中可见的名称
+    pub fn resolve(&self, name: &str, sheet: &str) -> Option<&Node> {
+        self.get(name, Some(sheet)).or_else(|| self.get(name, None))
+    }
+}
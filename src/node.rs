@@ -1,17 +1,52 @@
-use crate::token::Token;
+use crate::{
+    error::{ExcelError, Span},
+    reference::{AxisRef, CellRef},
+    token::Token,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ASTNode {
     Number(f32),
-    SignedNumber(f32, Box<ASTNode>),
+    SignedNumber(f32, Box<Node>),
     String(String),
     Bool(bool),
+    Error(ExcelError),
     Var(String),
-    Ref(String, Option<String>),
-    BinaryOp(Token, Box<ASTNode>, Box<ASTNode>),
-    Range(Box<ASTNode>, Box<ASTNode>),
-    RowRange(f32, f32, Option<String>),
-    ColRange(String, String, Option<String>),
-    UndeterminedRange(String, Box<ASTNode>),
-    Call(String, Vec<ASTNode>),
+    Ref(CellRef, Option<String>),
+    /// 动态数组公式的溢出区域，如 A1#
+    SpillRef(CellRef, Option<String>),
+    BinaryOp(Token, Box<Node>, Box<Node>),
+    Range(Box<Node>, Box<Node>),
+    RowRange(AxisRef, AxisRef, Option<String>),
+    ColRange(AxisRef, AxisRef, Option<String>),
+    UndeterminedRange(String, Box<Node>),
+    /// 隐式交集，如 @A1:A10
+    ImplicitIntersection(Box<Node>),
+    /// 三维引用，如 Jan:Dec!B5，依次为第一个、最后一个工作表和不带工作表的单元格或区域引用
+    Ref3D(String, String, Box<Node>),
+    Call(String, Vec<Node>),
+    /// 省略的函数参数，如 IF(A1,,1) 中的第二个参数
+    Missing,
+    /// 数组常量，按行保存，各行的长度相同
+    Array(Vec<Vec<Node>>),
+}
+
+/// 语法树节点，span 为节点对应的公式文本区间
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub kind: ASTNode,
+    pub span: Span,
+}
+
+impl Node {
+    pub fn new(kind: ASTNode, span: Span) -> Node {
+        Node { kind, span }
+    }
+}
+
+/// 比较时忽略 span，只比较语法结构
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
 }
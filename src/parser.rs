@@ -1,13 +1,23 @@
-use crate::{node::ASTNode, token::Token, utils::some};
+use crate::{
+    error::{Error, ErrorKind, Span},
+    node::{ASTNode, Node},
+    range::MAX_ROWS,
+    reference::{AxisRef, CellRef},
+    token::Token,
+    utils::some,
+};
 use std::cell::Cell;
 // #[derive(Debug)]
 pub struct Parser {
     pos: Cell<usize>,
-    tokens: Vec<Token>,
+    tokens: Vec<(Token, Span)>,
     compare_op: Vec<Token>,
     and_op: Vec<Token>,
     arith_op: Vec<Token>,
     term_op: Vec<Token>,
+    power_op: Vec<Token>,
+    union_op: Vec<Token>,
+    intersect_op: Vec<Token>,
 }
 
 impl Parser {
@@ -26,31 +36,93 @@ impl Parser {
             and_op: vec![Token::And],
             arith_op: vec![Token::Plus, Token::Minus],
             term_op: vec![Token::Mul, Token::Div],
+            power_op: vec![Token::Pow],
+            union_op: vec![Token::Comma],
+            intersect_op: vec![Token::Intersect],
         }
     }
 }
 
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Parser {
-    pub fn parse(&mut self, tokens: Vec<Token>) -> Result<ASTNode, String> {
+    pub fn parse(&mut self, tokens: Vec<(Token, Span)>) -> Result<Node, Error> {
         self.tokens = tokens;
         self.pos.set(0);
-        self.compare_expr()
+        let node = self.union_expr()?;
+        match self.current() {
+            Some(_) => Err(Error::new(ErrorKind::UnexpectedToken, self.current_span())),
+            None => Ok(node),
+        }
     }
 
     fn current(&self) -> Option<&Token> {
-        self.tokens.get(self.pos.get())
+        self.tokens.get(self.pos.get()).map(|(token, _)| token)
+    }
+
+    /// 当前 token 的区间，已到末尾时返回末尾处的空区间
+    fn current_span(&self) -> Span {
+        match self.tokens.get(self.pos.get()) {
+            Some((_, span)) => *span,
+            None => {
+                let end = self.tokens.last().map_or(0, |(_, span)| span.end);
+                Span::new(end, end)
+            }
+        }
+    }
+
+    /// 从 start 开始到上一个 token 结束的区间
+    fn span_since(&self, start: Span) -> Span {
+        match self.pos.get().checked_sub(1).and_then(|i| self.tokens.get(i)) {
+            Some((_, span)) => start.to(*span),
+            None => start,
+        }
+    }
+
+    /// 创建节点，区间从 start 到上一个 token 结束
+    fn node(&self, kind: ASTNode, start: Span) -> Node {
+        Node::new(kind, self.span_since(start))
+    }
+
+    fn eof(&self) -> Error {
+        Error::new(ErrorKind::UnexpectedEof, self.current_span())
     }
 
     fn advance(&self) {
         self.pos.set(self.pos.get() + 1);
     }
 
-    fn compare_expr(&self) -> Result<ASTNode, String> {
+    /// 引用的并集，如 (A1:A3,C1:C3)。函数参数之间的逗号是分隔符，
+    /// 因此并集只能出现在括号中或公式的最外层
+    fn union_expr(&self) -> Result<Node, Error> {
+        let start = self.current_span();
+        let mut node = self.compare_expr()?;
+        while let Some(token) = self.current() {
+            if some(&self.union_op, |t| t.matches(token)) {
+                self.advance();
+                let kind =
+                    ASTNode::BinaryOp(token.clone(), Box::new(node), Box::new(self.compare_expr()?));
+                node = self.node(kind, start);
+            } else {
+                break;
+            }
+        }
+        return Ok(node);
+    }
+
+    fn compare_expr(&self) -> Result<Node, Error> {
+        let start = self.current_span();
         let mut node = self.and_expr()?;
         while let Some(token) = self.current() {
             if some(&self.compare_op, |t| t.matches(token)) {
                 self.advance();
-                node = ASTNode::BinaryOp(token.clone(), Box::new(node), Box::new(self.and_expr()?));
+                let kind =
+                    ASTNode::BinaryOp(token.clone(), Box::new(node), Box::new(self.and_expr()?));
+                node = self.node(kind, start);
             } else {
                 break;
             }
@@ -58,13 +130,15 @@ impl Parser {
         return Ok(node);
     }
 
-    fn and_expr(&self) -> Result<ASTNode, String> {
+    fn and_expr(&self) -> Result<Node, Error> {
+        let start = self.current_span();
         let mut node = self.arith_expr()?;
         while let Some(token) = self.current() {
             if some(&self.and_op, |t| t.matches(token)) {
                 self.advance();
-                node =
+                let kind =
                     ASTNode::BinaryOp(token.clone(), Box::new(node), Box::new(self.arith_expr()?));
+                node = self.node(kind, start);
             } else {
                 break;
             }
@@ -72,13 +146,15 @@ impl Parser {
         return Ok(node);
     }
 
-    fn arith_expr(&self) -> Result<ASTNode, String> {
+    fn arith_expr(&self) -> Result<Node, Error> {
+        let start = self.current_span();
         let mut node = self.term_expr()?;
         while let Some(token) = self.current() {
             if some(&self.arith_op, |t| t.matches(token)) {
                 self.advance();
-                node =
+                let kind =
                     ASTNode::BinaryOp(token.clone(), Box::new(node), Box::new(self.term_expr()?));
+                node = self.node(kind, start);
             } else {
                 break;
             }
@@ -86,13 +162,32 @@ impl Parser {
         return Ok(node);
     }
 
-    fn term_expr(&self) -> Result<ASTNode, String> {
-        let mut node = self.factor_expr()?;
+    fn term_expr(&self) -> Result<Node, Error> {
+        let start = self.current_span();
+        let mut node = self.power_expr()?;
         while let Some(token) = self.current() {
             if some(&self.term_op, |t| t.matches(token)) {
                 self.advance();
-                node =
+                let kind =
+                    ASTNode::BinaryOp(token.clone(), Box::new(node), Box::new(self.power_expr()?));
+                node = self.node(kind, start);
+            } else {
+                break;
+            }
+        }
+        return Ok(node);
+    }
+
+    /// 乘方左结合，且优先级低于正负号，因此 -2^2 = 4
+    fn power_expr(&self) -> Result<Node, Error> {
+        let start = self.current_span();
+        let mut node = self.factor_expr()?;
+        while let Some(token) = self.current() {
+            if some(&self.power_op, |t| t.matches(token)) {
+                self.advance();
+                let kind =
                     ASTNode::BinaryOp(token.clone(), Box::new(node), Box::new(self.factor_expr()?));
+                node = self.node(kind, start);
             } else {
                 break;
             }
@@ -100,171 +195,318 @@ impl Parser {
         return Ok(node);
     }
 
-    fn factor_expr(&self) -> Result<ASTNode, String> {
+    fn factor_expr(&self) -> Result<Node, Error> {
+        let start = self.current_span();
         if let Some(token) = self.current() {
             if token.matches(&Token::Plus) {
                 self.advance();
-                return Ok(ASTNode::SignedNumber(1_f32, Box::new(self.range_expr()?)));
+                let kind = ASTNode::SignedNumber(1_f32, Box::new(self.factor_expr()?));
+                return Ok(self.node(kind, start));
             } else if token.matches(&Token::Minus) {
                 self.advance();
-                return Ok(ASTNode::SignedNumber(-1_f32, Box::new(self.range_expr()?)));
+                let kind = ASTNode::SignedNumber(-1_f32, Box::new(self.factor_expr()?));
+                return Ok(self.node(kind, start));
             } else {
-                return Ok(self.range_expr()?);
+                return self.intersect_expr();
             }
         } else {
-            return Err("Unexpected EOF".to_string());
+            return Err(self.eof());
         }
     }
 
-    fn range_expr(&self) -> Result<ASTNode, String> {
-        let left = self.atom_expr()?;
-        match self.current() {
-            Some(token) => {
-                if token.matches(&Token::Colon) {
-                    self.advance(); // 跳过 :
-                    match left {
-                        ASTNode::Var(_) | ASTNode::Number(_) => self.make_row_or_col_range(left, None),
-                        ASTNode::UndeterminedRange(sheet, node) => self.make_row_or_col_range(*node, Some(sheet)),
-                        ASTNode::Ref(_, _) => match self.atom_expr()? {
-                            right @ ASTNode::Ref(_, _) => {
-                                Ok(ASTNode::Range(Box::new(left), Box::new(right)))
-                            }
-                            _ => return Err("Range not valid. The right side of the range should be a cell reference".to_string()),
-                        },
-                        _ => return Err("Range not valid. The left side of the range should be a row reference or a column reference or a cell reference".to_string()),
-                    }
-                } else {
-                    return Ok(left);
-                }
+    /// 引用的交集，如 A1:C5 B2:D4，优先级高于正负号、低于 :
+    fn intersect_expr(&self) -> Result<Node, Error> {
+        let start = self.current_span();
+        let mut node = self.range_expr()?;
+        while let Some(token) = self.current() {
+            if some(&self.intersect_op, |t| t.matches(token)) {
+                self.advance();
+                let kind =
+                    ASTNode::BinaryOp(token.clone(), Box::new(node), Box::new(self.range_expr()?));
+                node = self.node(kind, start);
+            } else {
+                break;
             }
-            None => Ok(left),
         }
+        return Ok(node);
+    }
+
+    /// 区域运算符 :，两侧可以是任意返回引用的表达式，如 A1:INDEX(B:B,3)，可以连续使用
+    fn range_expr(&self) -> Result<Node, Error> {
+        let start = self.current_span();
+        let mut node = self.atom_expr()?;
+        while let Some(Token::Colon) = self.current() {
+            self.advance(); // 跳过 :
+            let right = self.atom_expr()?;
+            node = self.make_range(node, right, start)?;
+        }
+        Ok(node)
     }
 
-    fn atom_expr(&self) -> Result<ASTNode, String> {
+    fn atom_expr(&self) -> Result<Node, Error> {
+        let start = self.current_span();
         match self.current() {
             Some(token) => match token {
                 Token::Number(n) => {
                     self.advance();
-                    Ok(ASTNode::Number(*n))
+                    Ok(self.node(ASTNode::Number(*n), start))
                 }
                 Token::String(s) => {
                     self.advance();
-                    Ok(ASTNode::String(s.clone()))
+                    Ok(self.node(ASTNode::String(s.clone()), start))
                 }
                 Token::Bool(b) => {
                     self.advance();
-                    Ok(ASTNode::Bool(*b))
+                    Ok(self.node(ASTNode::Bool(*b), start))
                 }
-                Token::Ref(r) => {
+                Token::Error(e) => {
                     self.advance();
-                    Ok(ASTNode::Ref(r.clone(), None))
+                    Ok(self.node(ASTNode::Error(*e), start))
                 }
+                Token::Ref(r) => Ok(self.cell_ref(*r, None, start)),
                 Token::Lparen => self.sub_expr(),
+                Token::Lbrace => self.array_expr(),
+                Token::At => {
+                    self.advance();
+                    let kind = ASTNode::ImplicitIntersection(Box::new(self.range_expr()?));
+                    Ok(self.node(kind, start))
+                }
                 Token::Var(var) => {
                     self.advance();
                     match self.current() {
                         Some(token) => {
                             if token.matches(&Token::Lparen) {
-                                self.call_expr(var.clone())
+                                self.call_expr(var.clone(), start)
                             } else {
-                                Ok(ASTNode::Var(var.clone()))
+                                Ok(self.node(ASTNode::Var(var.clone()), start))
                             }
                         }
-                        None => Ok(ASTNode::Var(var.clone())),
+                        None => Ok(self.node(ASTNode::Var(var.clone()), start)),
                     }
                 }
+                Token::Sheet(sheet) if sheet.contains(':') => self.sheets_ref(sheet, start),
                 Token::Sheet(sheet) => {
                     self.advance();
                     match self.current() {
-                        Some(token) => match token {
-                            Token::Csref => {
-                                self.advance();
-                                match self.current() {
-                                    Some(token) => match token {
-                                        Token::Ref(r) => {
-                                            self.advance();
-                                            Ok(ASTNode::Ref(r.clone(), Some(sheet.clone())))
-                                        }
-                                        Token::Var(v) => {
-                                            self.advance();
-                                            Ok(ASTNode::UndeterminedRange(
-                                                sheet.clone(),
-                                                Box::new(ASTNode::Var(v.clone())),
-                                            ))
-                                        }
-                                        Token::Number(n) => {
-                                            self.advance();
-                                            Ok(ASTNode::UndeterminedRange(
-                                                sheet.clone(),
-                                                Box::new(ASTNode::Number(*n)),
-                                            ))
-                                        }
-                                        _ => Err("Not valid cross sheet reference".to_string()),
-                                    },
-                                    None => Err("Unexpected EOF".to_string()),
-                                }
+                        Some(Token::Csref) => {
+                            self.advance();
+                            match self.current() {
+                                Some(token) => match token {
+                                    Token::Ref(r) => Ok(self.cell_ref(*r, Some(sheet.clone()), start)),
+                                    Token::Var(v) => {
+                                        let inner = self.current_span();
+                                        self.advance();
+                                        let kind = ASTNode::UndeterminedRange(
+                                            sheet.clone(),
+                                            Box::new(Node::new(ASTNode::Var(v.clone()), inner)),
+                                        );
+                                        Ok(self.node(kind, start))
+                                    }
+                                    Token::Number(n) => {
+                                        let inner = self.current_span();
+                                        self.advance();
+                                        let kind = ASTNode::UndeterminedRange(
+                                            sheet.clone(),
+                                            Box::new(Node::new(ASTNode::Number(*n), inner)),
+                                        );
+                                        Ok(self.node(kind, start))
+                                    }
+                                    _ => Err(Error::new(ErrorKind::InvalidSheetRef, start.to(self.current_span()))),
+                                },
+                                None => Err(self.eof()),
                             }
-                            _ => Err("Expect '!'".to_string()),
-                        },
-                        None => Err("Unexpected EOF".to_string()),
+                        }
+                        // 工作表名称之后应为 !
+                        _ => Err(Error::new(ErrorKind::InvalidSheetRef, start)),
                     }
                 }
-                _ => Err("Unexpected token".to_string()),
+                _ => Err(Error::new(ErrorKind::UnexpectedToken, self.current_span())),
             },
-            None => Err("Unexpected EOF".to_string()),
+            None => Err(self.eof()),
         }
     }
 
-    fn sub_expr(&self) -> Result<ASTNode, String> {
+    /// 三维引用，如 Jan:Dec!B5、'Q1:Q4'!A1:B2，工作表之后只能是单元格、区域或整行整列
+    fn sheets_ref(&self, sheets: &str, start: Span) -> Result<Node, Error> {
+        let (first, last) = match sheets.split_once(':') {
+            Some((first, last)) if !first.is_empty() && !last.is_empty() => (first, last),
+            _ => return Err(Error::new(ErrorKind::InvalidSheetRef, start)),
+        };
+        self.advance();
+        match self.current() {
+            Some(Token::Csref) => self.advance(),
+            _ => return Err(Error::new(ErrorKind::InvalidSheetRef, start)),
+        }
+        let inner = self.range_expr()?;
+        let valid = match &inner.kind {
+            ASTNode::Ref(_, None) | ASTNode::RowRange(_, _, None) | ASTNode::ColRange(_, _, None) => true,
+            ASTNode::Range(left, right) => {
+                matches!((&left.kind, &right.kind), (ASTNode::Ref(_, None), ASTNode::Ref(_, None)))
+            }
+            _ => false,
+        };
+        if !valid {
+            return Err(Error::new(ErrorKind::InvalidSheetRef, self.span_since(start)));
+        }
+        let kind = ASTNode::Ref3D(first.to_string(), last.to_string(), Box::new(inner));
+        Ok(self.node(kind, start))
+    }
+
+    /// 单元格引用，紧跟 # 时为溢出区域的引用
+    fn cell_ref(&self, cell: CellRef, sheet: Option<String>, start: Span) -> Node {
+        self.advance();
+        if let Some(Token::Spill) = self.current() {
+            self.advance();
+            return self.node(ASTNode::SpillRef(cell, sheet), start);
+        }
+        self.node(ASTNode::Ref(cell, sheet), start)
+    }
+
+    fn sub_expr(&self) -> Result<Node, Error> {
+        let lparen = self.current_span();
         self.advance(); //跳过左括号
-        let node = self.compare_expr()?;
+        let node = self.union_expr()?;
         match self.current() {
             Some(Token::Rparen) => {
                 self.advance();
                 Ok(node)
             }
-            _ => Err("Unmatched parenthesis".to_string()),
+            _ => Err(Error::new(ErrorKind::UnmatchedParen, lparen)),
         }
     }
 
-    fn call_expr(&self, name: String) -> Result<ASTNode, String> {
+    /// 数组常量，如 {1,2,3;4,5,6}，逗号分隔列，分号分隔行
+    fn array_expr(&self) -> Result<Node, Error> {
+        let start = self.current_span();
+        self.advance(); // 跳过 {
+        let mut rows = vec![vec![]];
+        loop {
+            rows.last_mut().unwrap().push(self.array_item()?);
+            match self.current() {
+                Some(Token::Comma) => self.advance(),
+                Some(Token::Semicolon) => {
+                    self.advance();
+                    rows.push(vec![]);
+                }
+                Some(Token::Rbrace) => {
+                    self.advance();
+                    break;
+                }
+                Some(_) => return Err(Error::new(ErrorKind::InvalidArray, self.current_span())),
+                None => return Err(self.eof()),
+            }
+        }
+        if rows.iter().any(|row| row.len() != rows[0].len()) {
+            return Err(Error::new(ErrorKind::InvalidArray, self.span_since(start)));
+        }
+        Ok(self.node(ASTNode::Array(rows), start))
+    }
+
+    /// 数组中的元素只能是常量，数字前可以有正负号
+    fn array_item(&self) -> Result<Node, Error> {
+        let start = self.current_span();
+        let sign = match self.current() {
+            Some(Token::Minus) => Some(-1_f32),
+            Some(Token::Plus) => Some(1_f32),
+            _ => None,
+        };
+        if sign.is_some() {
+            self.advance();
+        }
+        let kind = match (self.current(), sign) {
+            (Some(Token::Number(n)), sign) => ASTNode::Number(sign.unwrap_or(1_f32) * n),
+            (Some(Token::String(s)), None) => ASTNode::String(s.clone()),
+            (Some(Token::Bool(b)), None) => ASTNode::Bool(*b),
+            (Some(Token::Error(e)), None) => ASTNode::Error(*e),
+            (None, _) => return Err(self.eof()),
+            _ => return Err(Error::new(ErrorKind::InvalidArray, self.current_span())),
+        };
+        self.advance();
+        Ok(self.node(kind, start))
+    }
+
+    fn call_expr(&self, name: String, start: Span) -> Result<Node, Error> {
+        let lparen = self.current_span();
         self.advance();
         let mut args = vec![];
+        if let Some(Token::Rparen) = self.current() {
+            self.advance();
+            return Ok(self.node(ASTNode::Call(name, args), start));
+        }
         loop {
+            // 逗号之间没有内容时是省略的参数，如 IF(A1,,1)
             match self.current() {
-                Some(token) => match token {
-                    Token::Rparen => {
-                        self.advance();
-                        break Ok(ASTNode::Call(name, args));
-                    }
-                    Token::Comma => {
-                        self.advance();
-                        continue;
-                    }
-                    _ => args.push(self.compare_expr()?),
-                },
-                None => break Err("Unexpected EOF".to_string()),
+                Some(Token::Comma) | Some(Token::Rparen) => {
+                    let at = self.current_span().start;
+                    args.push(Node::new(ASTNode::Missing, Span::new(at, at)));
+                }
+                Some(_) => args.push(self.compare_expr()?),
+                None => break Err(Error::new(ErrorKind::UnmatchedParen, lparen)),
+            }
+            // 参数之后必须是逗号或右括号，如 SUM(1 2) 是错误的
+            match self.current() {
+                Some(Token::Comma) => self.advance(),
+                Some(Token::Rparen) => {
+                    self.advance();
+                    break Ok(self.node(ASTNode::Call(name, args), start));
+                }
+                Some(_) => break Err(Error::new(ErrorKind::UnexpectedToken, self.current_span())),
+                None => break Err(Error::new(ErrorKind::UnmatchedParen, lparen)),
             }
         }
     }
 
-    /// 创建行或列范围
-    fn make_row_or_col_range(
-        &self,
-        left: ASTNode,
-        sheet: Option<String>,
-    ) -> Result<ASTNode, String> {
-        match left {
-            ASTNode::Number(f) => match self.atom_expr()? {
-                ASTNode::Number(t) => Ok(ASTNode::RowRange(f, t, sheet)),
-                _ => Err("Range not valid. The right side of the range must be a row reference".to_string()),
-            },
-            ASTNode::Var(f) => match self.atom_expr()? {
-                ASTNode::Var(t) => Ok(ASTNode::ColRange(f, t, sheet)),
-                _ => Err("Range not valid. The right side of the range must be a col reference".to_string()),
-            },
-            _ => Err("Range not valid. The left side of the range must be a row reference or a column referencee".to_string()),
+    /// 创建行或列范围，两侧为行号（如 1、$1）或列名（如 A、$A）
+    /// 两侧同为行号或列名时是整行或整列的区域，如 1:3、Sheet1!A:C；其余情况两侧都必须是引用
+    fn make_range(&self, left: Node, right: Node, start: Span) -> Result<Node, Error> {
+        let (axis, sheet) = match &left.kind {
+            ASTNode::UndeterminedRange(sheet, inner) => (&inner.kind, Some(sheet.clone())),
+            kind => (kind, None),
+        };
+        if let (Some(from), Some(to)) = (row_axis(axis), row_axis(&right.kind)) {
+            return Ok(self.node(ASTNode::RowRange(from, to, sheet), start));
         }
+        if let (Some(from), Some(to)) = (col_axis(axis), col_axis(&right.kind)) {
+            return Ok(self.node(ASTNode::ColRange(from, to, sheet), start));
+        }
+        if is_reference(&left.kind) && is_reference(&right.kind) {
+            return Ok(self.node(ASTNode::Range(Box::new(left), Box::new(right)), start));
+        }
+        Err(Error::new(ErrorKind::InvalidRange, self.span_since(start)))
+    }
+}
+
+/// 可能返回引用的表达式，常量不能作为区域的一端
+fn is_reference(kind: &ASTNode) -> bool {
+    match kind {
+        ASTNode::BinaryOp(op, _, _) => matches!(op, Token::Intersect | Token::Comma),
+        ASTNode::Ref(..)
+        | ASTNode::SpillRef(..)
+        | ASTNode::Range(..)
+        | ASTNode::RowRange(..)
+        | ASTNode::ColRange(..)
+        | ASTNode::UndeterminedRange(..)
+        | ASTNode::Ref3D(..)
+        | ASTNode::Var(_)
+        | ASTNode::Call(..)
+        | ASTNode::Error(_) => true,
+        _ => false,
+    }
+}
+
+fn row_axis(kind: &ASTNode) -> Option<AxisRef> {
+    match kind {
+        ASTNode::Number(n) if *n >= 1_f32 && n.fract() == 0_f32 && *n as usize <= MAX_ROWS => {
+            Some(AxisRef::new(*n as usize, false))
+        }
+        ASTNode::Var(v) => AxisRef::parse_row(v),
+        _ => None,
+    }
+}
+
+fn col_axis(kind: &ASTNode) -> Option<AxisRef> {
+    match kind {
+        ASTNode::Var(v) => AxisRef::parse_col(v),
+        _ => None,
     }
 }
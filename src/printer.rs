@@ -0,0 +1,222 @@
+use crate::{
+    node::{ASTNode, Node},
+    token::Token,
+    utils::{quote_sheet_name, quote_sheet_span},
+};
+
+/// 将语法树输出为公式文本（不含开头的 =），只在运算优先级需要时添加括号。
+/// 输出的文本重新解析后得到相同的语法树
+pub fn to_formula(node: &Node) -> String {
+    let mut text = String::new();
+    write_node(&mut text, node);
+    text
+}
+
+/// 运算符的优先级，和解析器中各层产生式一一对应，数值越大结合得越紧
+pub(crate) fn precedence(kind: &ASTNode) -> u8 {
+    match kind {
+        ASTNode::BinaryOp(op, _, _) => op_precedence(op),
+        ASTNode::SignedNumber(_, _) | ASTNode::ImplicitIntersection(_) => 6,
+        ASTNode::Range(_, _) | ASTNode::RowRange(..) | ASTNode::ColRange(..) => 8,
+        _ => 9,
+    }
+}
+
+pub(crate) fn op_precedence(op: &Token) -> u8 {
+    match op {
+        Token::Comma => 0,
+        Token::Ee | Token::Ne | Token::Gt | Token::Lt | Token::Gte | Token::Lte => 1,
+        Token::And => 2,
+        Token::Plus | Token::Minus => 3,
+        Token::Mul | Token::Div => 4,
+        Token::Intersect => 7,
+        _ => 5,
+    }
+}
+
+pub(crate) fn op_text(op: &Token) -> &'static str {
+    match op {
+        Token::Plus => "+",
+        Token::Minus => "-",
+        Token::Mul => "*",
+        Token::Div => "/",
+        Token::Pow => "^",
+        Token::Ee => "=",
+        Token::Ne => "<>",
+        Token::Gt => ">",
+        Token::Lt => "<",
+        Token::Gte => ">=",
+        Token::Lte => "<=",
+        Token::And => "&",
+        Token::Intersect => " ",
+        Token::Comma => ",",
+        _ => "?",
+    }
+}
+
+/// 优先级低于 min 时加上括号
+fn write_operand(text: &mut String, node: &Node, min: u8) {
+    if precedence(&node.kind) < min {
+        text.push('(');
+        write_node(text, node);
+        text.push(')');
+    } else {
+        write_node(text, node);
+    }
+}
+
+/// 交集两侧的操作数至少需要的优先级。常量不能直接作为交集的操作数，如 A1 (1) 去掉括号后无法解析，
+/// 这时总是加上括号
+pub(crate) fn intersect_min(node: &Node, min: u8) -> u8 {
+    match node.kind {
+        ASTNode::Number(_)
+        | ASTNode::String(_)
+        | ASTNode::Bool(_)
+        | ASTNode::Error(_)
+        | ASTNode::Array(_) => 10,
+        _ => min,
+    }
+}
+
+/// 输出时位于最右侧的操作数
+fn last_operand(node: &Node) -> &Node {
+    match &node.kind {
+        ASTNode::BinaryOp(_, _, right) | ASTNode::Range(_, right) => last_operand(right),
+        ASTNode::SignedNumber(_, inner) | ASTNode::ImplicitIntersection(inner) => {
+            last_operand(inner)
+        }
+        _ => node,
+    }
+}
+
+/// text 中从 start 开始的左侧操作数以名称结尾时加上括号，避免名称和右侧连在一起解析：
+/// 名称之后的左括号会被当作函数调用，名称之后的 :工作表名! 会被当作三维引用
+pub(crate) fn separate_name(text: &mut String, start: usize, left: &Node) {
+    if !text.ends_with(')') && matches!(last_operand(left).kind, ASTNode::Var(_)) {
+        text.insert(start, '(');
+        text.push(')');
+    }
+}
+
+/// 引用以工作表名开头，如 Sheet1!A1、Jan:Mar!B5
+pub(crate) fn has_sheet(node: &Node) -> bool {
+    matches!(
+        &node.kind,
+        ASTNode::Ref(_, Some(_))
+            | ASTNode::SpillRef(_, Some(_))
+            | ASTNode::UndeterminedRange(..)
+            | ASTNode::Ref3D(..)
+    )
+}
+
+fn write_sheet(text: &mut String, sheet: Option<&str>) {
+    if let Some(sheet) = sheet {
+        text.push_str(&quote_sheet_name(sheet));
+        text.push('!');
+    }
+}
+
+fn write_node(text: &mut String, node: &Node) {
+    match &node.kind {
+        ASTNode::Number(n) => text.push_str(&n.to_string()),
+        // 正负号之后是另一个正负号、区域或更紧的表达式
+        ASTNode::SignedNumber(sign, inner) => {
+            text.push(if *sign < 0_f32 { '-' } else { '+' });
+            write_operand(text, inner, 6);
+        }
+        ASTNode::ImplicitIntersection(inner) => {
+            text.push('@');
+            write_operand(text, inner, 8);
+        }
+        ASTNode::String(s) => {
+            text.push('"');
+            text.push_str(&s.replace('"', "\"\""));
+            text.push('"');
+        }
+        ASTNode::Bool(b) => text.push_str(if *b { "TRUE" } else { "FALSE" }),
+        ASTNode::Missing => {}
+        ASTNode::Error(e) => text.push_str(e.as_str()),
+        ASTNode::Var(name) => text.push_str(name),
+        ASTNode::Ref(cell, sheet) => {
+            write_sheet(text, sheet.as_deref());
+            text.push_str(&cell.to_string());
+        }
+        ASTNode::SpillRef(cell, sheet) => {
+            write_sheet(text, sheet.as_deref());
+            text.push_str(&cell.to_string());
+            text.push('#');
+        }
+        // 二元运算都是左结合的，右侧优先级相同时也需要括号。
+        // 交集的右侧以括号开头时，左侧的名称需要括号，如 (Data) (B1,C1)
+        ASTNode::BinaryOp(op, left, right) => {
+            let p = op_precedence(op);
+            let (left_min, right_min) = match op {
+                Token::Intersect => (intersect_min(left, p), intersect_min(right, p + 1)),
+                _ => (p, p + 1),
+            };
+            let mut right_text = String::new();
+            write_operand(&mut right_text, right, right_min);
+            let start = text.len();
+            write_operand(text, left, left_min);
+            if *op == Token::Intersect && right_text.starts_with('(') {
+                separate_name(text, start, left);
+            }
+            text.push_str(op_text(op));
+            text.push_str(&right_text);
+        }
+        // 右侧带工作表名时，左侧的名称需要括号，如 (Jan):Mar!B5
+        ASTNode::Range(left, right) => {
+            let start = text.len();
+            write_operand(text, left, 8);
+            if has_sheet(right) {
+                separate_name(text, start, left);
+            }
+            text.push(':');
+            write_operand(text, right, 9);
+        }
+        ASTNode::RowRange(from, to, sheet) => {
+            write_sheet(text, sheet.as_deref());
+            text.push_str(&format!("{}:{}", from.row_text(), to.row_text()));
+        }
+        ASTNode::ColRange(from, to, sheet) => {
+            write_sheet(text, sheet.as_deref());
+            text.push_str(&format!("{}:{}", from.col_text(), to.col_text()));
+        }
+        ASTNode::UndeterminedRange(sheet, inner) => {
+            write_sheet(text, Some(sheet));
+            write_node(text, inner);
+        }
+        ASTNode::Ref3D(first, last, inner) => {
+            text.push_str(&quote_sheet_span(first, last));
+            text.push('!');
+            write_node(text, inner);
+        }
+        ASTNode::Array(rows) => {
+            text.push('{');
+            for (i, row) in rows.iter().enumerate() {
+                if i > 0 {
+                    text.push(';');
+                }
+                for (j, item) in row.iter().enumerate() {
+                    if j > 0 {
+                        text.push(',');
+                    }
+                    write_node(text, item);
+                }
+            }
+            text.push('}');
+        }
+        ASTNode::Call(name, args) => {
+            text.push_str(name);
+            text.push('(');
+            // 参数中的并集需要括号，否则逗号会被当作参数分隔符
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    text.push(',');
+                }
+                write_operand(text, arg, 1);
+            }
+            text.push(')');
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use crate::{
+    interpreter::ComputeResult,
+    range::{CellRange, MAX_COLS, MAX_ROWS},
+};
+
+/// 单元格数据来源，由调用方实现，用于解析公式中的单元格引用
+pub trait DataProvider {
+    /// 读取指定工作表中单元格的值，行列均从 1 开始，空单元格返回 None
+    fn cell(&self, sheet: &str, row: usize, col: usize) -> Option<ComputeResult>;
+
+    /// 工作表已用区域的 (最大行, 最大列)，遍历整行整列时据此裁剪
+    fn used_range(&self, _sheet: &str) -> (usize, usize) {
+        (MAX_ROWS, MAX_COLS)
+    }
+
+    /// 以该单元格为左上角的动态数组溢出区域，用于解析 A1# 引用；
+    /// 单元格不是动态数组公式或无法溢出时返回 None
+    fn spill_range(&self, _sheet: &str, _row: usize, _col: usize) -> Option<CellRange> {
+        None
+    }
+
+    /// 按顺序排列的所有工作表，用于解析 Jan:Dec!B5 这样的三维引用
+    fn sheet_names(&self) -> Vec<String> {
+        vec![]
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{interpreter::ComputeResult, provider::DataProvider, utils::same_sheet};
+
+/// 工作表的最大行数
+pub const MAX_ROWS: usize = 1_048_576;
+/// 工作表的最大列数
+pub const MAX_COLS: usize = 16_384;
+
+/// 单元格区域的引用，只记录边界，单元格的值在遍历时才向数据来源读取
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellRange {
+    pub sheet: String,
+    pub top: usize,
+    pub left: usize,
+    pub bottom: usize,
+    pub right: usize,
+}
+
+impl CellRange {
+    /// 由任意两个角创建区域，行列均从 1 开始
+    pub fn new(sheet: String, (r1, c1): (usize, usize), (r2, c2): (usize, usize)) -> CellRange {
+        CellRange {
+            sheet,
+            top: r1.min(r2),
+            left: c1.min(c2),
+            bottom: r1.max(r2),
+            right: c1.max(c2),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.bottom - self.top + 1
+    }
+
+    pub fn cols(&self) -> usize {
+        self.right - self.left + 1
+    }
+
+    pub fn contains(&self, sheet: &str, row: usize, col: usize) -> bool {
+        (self.top..=self.bottom).contains(&row)
+            && (self.left..=self.right).contains(&col)
+            && same_sheet(&self.sheet, sheet)
+    }
+
+    /// 两个区域的重叠部分，不在同一工作表或没有重叠时返回 None
+    pub fn intersect(&self, other: &CellRange) -> Option<CellRange> {
+        let top = self.top.max(other.top);
+        let left = self.left.max(other.left);
+        let bottom = self.bottom.min(other.bottom);
+        let right = self.right.min(other.right);
+        if top > bottom || left > right || !same_sheet(&self.sheet, &other.sheet) {
+            return None;
+        }
+        Some(CellRange {
+            sheet: self.sheet.clone(),
+            top,
+            left,
+            bottom,
+            right,
+        })
+    }
+
+    /// 同时包含两个区域的最小区域，不在同一工作表时返回 None
+    pub fn bounding(&self, other: &CellRange) -> Option<CellRange> {
+        if !same_sheet(&self.sheet, &other.sheet) {
+            return None;
+        }
+        Some(CellRange {
+            sheet: self.sheet.clone(),
+            top: self.top.min(other.top),
+            left: self.left.min(other.left),
+            bottom: self.bottom.max(other.bottom),
+            right: self.right.max(other.right),
+        })
+    }
+
+    /// 按区域内的偏移（从 0 开始）读取单元格，空单元格返回 Empty
+    pub fn get(&self, provider: &dyn DataProvider, row: usize, col: usize) -> ComputeResult {
+        provider
+            .cell(&self.sheet, self.top + row, self.left + col)
+            .unwrap_or(ComputeResult::Empty)
+    }
+
+    /// 区域内所有单元格的值，整行整列会先裁剪到工作表的已用区域，其余区域保持原有大小
+    pub fn values(&self, provider: &dyn DataProvider) -> Vec<Vec<ComputeResult>> {
+        let (max_row, max_col) = provider.used_range(&self.sheet);
+        let bottom = match self.bottom {
+            MAX_ROWS => max_row.max(self.top),
+            bottom => bottom,
+        };
+        let right = match self.right {
+            MAX_COLS => max_col.max(self.left),
+            right => right,
+        };
+        (self.top..=bottom)
+            .map(|row| {
+                (self.left..=right)
+                    .map(|col| self.get(provider, row - self.top, col - self.left))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// 按行优先顺序遍历区域内的非空单元格，整行整列会先裁剪到工作表的已用区域
+    pub fn cells<'p>(
+        &self,
+        provider: &'p dyn DataProvider,
+    ) -> impl Iterator<Item = (usize, usize, ComputeResult)> + 'p {
+        let (max_row, max_col) = provider.used_range(&self.sheet);
+        let sheet = self.sheet.clone();
+        let (left, right) = (self.left, self.right.min(max_col));
+        (self.top..=self.bottom.min(max_row)).flat_map(move |row| {
+            let sheet = sheet.clone();
+            (left..=right).filter_map(move |col| {
+                provider
+                    .cell(&sheet, row, col)
+                    .map(|value| (row, col, value))
+            })
+        })
+    }
+}
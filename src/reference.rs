@@ -0,0 +1,96 @@
+use crate::{
+    range::{MAX_COLS, MAX_ROWS},
+    utils::{col_index, col_name},
+};
+use std::fmt;
+
+/// 行号或列号，从 1 开始，abs 表示带 $ 的绝对引用
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AxisRef {
+    pub index: usize,
+    pub abs: bool,
+}
+
+impl AxisRef {
+    pub fn new(index: usize, abs: bool) -> AxisRef {
+        AxisRef { index, abs }
+    }
+
+    /// 解析行号，如 3、$3
+    pub fn parse_row(text: &str) -> Option<AxisRef> {
+        let (text, abs) = split_abs(text);
+        if text.is_empty() || !text.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        match text.parse::<usize>() {
+            Ok(row) if (1..=MAX_ROWS).contains(&row) => Some(AxisRef::new(row, abs)),
+            _ => None,
+        }
+    }
+
+    /// 解析列名，如 C、$C
+    pub fn parse_col(text: &str) -> Option<AxisRef> {
+        let (text, abs) = split_abs(text);
+        match col_index(text) {
+            Some(col) if col <= MAX_COLS => Some(AxisRef::new(col, abs)),
+            _ => None,
+        }
+    }
+
+    /// 作为行号输出，如 $3
+    pub fn row_text(&self) -> String {
+        format!("{}{}", if self.abs { "$" } else { "" }, self.index)
+    }
+
+    /// 作为列名输出，如 $C
+    pub fn col_text(&self) -> String {
+        format!(
+            "{}{}",
+            if self.abs { "$" } else { "" },
+            col_name(self.index)
+        )
+    }
+}
+
+fn split_abs(text: &str) -> (&str, bool) {
+    match text.strip_prefix('$') {
+        Some(rest) => (rest, true),
+        None => (text, false),
+    }
+}
+
+/// 单元格引用，如 A1、$A$1，行列分别记录是否为绝对引用
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CellRef {
+    pub row: AxisRef,
+    pub col: AxisRef,
+}
+
+impl CellRef {
+    pub fn new(row: AxisRef, col: AxisRef) -> CellRef {
+        CellRef { row, col }
+    }
+
+    /// 解析形如 A1、$A1、A$1、$A$1 的地址，超出工作表范围时返回 None
+    pub fn parse(text: &str) -> Option<CellRef> {
+        let split = text
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| *c == '$' || c.is_ascii_digit())?
+            .0;
+        let col = AxisRef::parse_col(&text[..split])?;
+        let row = AxisRef::parse_row(&text[split..])?;
+        Some(CellRef::new(row, col))
+    }
+
+    /// 从 1 开始的 (行, 列)
+    pub fn position(&self) -> (usize, usize) {
+        (self.row.index, self.col.index)
+    }
+}
+
+impl fmt::Display for CellRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.col.col_text(), self.row.row_text())
+    }
+}
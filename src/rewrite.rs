@@ -0,0 +1,474 @@
+use crate::{
+    error::{ExcelError, Span},
+    interpreter::CellPosition,
+    node::{ASTNode, Node},
+    range::{CellRange, MAX_COLS, MAX_ROWS},
+    reference::{AxisRef, CellRef},
+    utils::{quote_sheet_name, quote_sheet_span, same_sheet},
+};
+
+/// 公式中的一个引用，不含工作表
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Reference {
+    Cell(CellRef),
+    Area(CellRef, CellRef),
+    Rows(AxisRef, AxisRef),
+    Cols(AxisRef, AxisRef),
+}
+
+/// 改写公式中的引用。edit 接收引用及其工作表，返回新的引用，返回 None 时引用替换为 #REF!。
+/// 只替换发生变化的引用，公式的其余部分（空格、大小写等）保持原样
+pub(crate) fn rewrite_references(
+    formula: &str,
+    node: &Node,
+    edit: &mut dyn FnMut(&Reference, Option<&str>) -> Option<Reference>,
+) -> String {
+    let mut edits = vec![];
+    collect_edits(formula, node, edit, &mut edits);
+    splice(formula, edits)
+}
+
+/// 用 edits 替换公式文本中对应的区间，各区间互不重叠
+fn splice(formula: &str, mut edits: Vec<(Span, String)>) -> String {
+    edits.sort_by_key(|(span, _)| span.start);
+    let mut text = String::with_capacity(formula.len());
+    let mut last = 0;
+    for (span, replacement) in edits {
+        text.push_str(&formula[last..span.start]);
+        text.push_str(&replacement);
+        last = span.end;
+    }
+    text.push_str(&formula[last..]);
+    text
+}
+
+fn collect_edits(
+    formula: &str,
+    node: &Node,
+    edit: &mut dyn FnMut(&Reference, Option<&str>) -> Option<Reference>,
+    edits: &mut Vec<(Span, String)>,
+) {
+    let (reference, sheet) = match &node.kind {
+        ASTNode::Ref(cell, sheet) | ASTNode::SpillRef(cell, sheet) => {
+            (Reference::Cell(*cell), sheet)
+        }
+        ASTNode::Range(left, right) => match (&left.kind, &right.kind) {
+            (ASTNode::Ref(from, s1), ASTNode::Ref(to, s2)) => (
+                Reference::Area(*from, *to),
+                if s1.is_some() { s1 } else { s2 },
+            ),
+            // 由其他引用表达式构成的区域，分别调整两侧
+            _ => {
+                collect_edits(formula, left, edit, edits);
+                return collect_edits(formula, right, edit, edits);
+            }
+        },
+        ASTNode::RowRange(from, to, sheet) => (Reference::Rows(*from, *to), sheet),
+        ASTNode::ColRange(from, to, sheet) => (Reference::Cols(*from, *to), sheet),
+        ASTNode::SignedNumber(_, node) | ASTNode::ImplicitIntersection(node) => {
+            return collect_edits(formula, node, edit, edits)
+        }
+        // 三维引用跨越多个工作表，不随单个工作表的行列变化调整，复制公式时照常平移。
+        // Jan:Dec 不是任何工作表的名称
+        ASTNode::Ref3D(first, last, inner) => {
+            let sheets = format!("{}:{}", first, last);
+            let mut edit = |reference: &Reference, _: Option<&str>| edit(reference, Some(&sheets));
+            return collect_edits(formula, inner, &mut edit, edits);
+        }
+        ASTNode::BinaryOp(_, left, right) => {
+            collect_edits(formula, left, edit, edits);
+            return collect_edits(formula, right, edit, edits);
+        }
+        ASTNode::Call(_, args) => {
+            for arg in args {
+                collect_edits(formula, arg, edit, edits);
+            }
+            return;
+        }
+        _ => return,
+    };
+    match (edit(&reference, sheet.as_deref()), &node.kind) {
+        (None, _) => edits.push((node.span, ExcelError::Ref.to_string())),
+        (Some(new), _) if new == reference => {}
+        (Some(Reference::Cell(cell)), ASTNode::SpillRef(..)) => {
+            // 保留地址之后的 #
+            let span = address_span(formula, node.span);
+            edits.push((Span::new(span.start, span.end - 1), cell.to_string()))
+        }
+        (Some(Reference::Cell(cell)), _) => {
+            edits.push((address_span(formula, node.span), cell.to_string()))
+        }
+        (Some(Reference::Area(from, to)), ASTNode::Range(left, right)) => {
+            edits.push((address_span(formula, left.span), from.to_string()));
+            edits.push((address_span(formula, right.span), to.to_string()));
+        }
+        (Some(Reference::Rows(from, to)), _) => edits.push((
+            address_span(formula, node.span),
+            format!("{}:{}", from.row_text(), to.row_text()),
+        )),
+        (Some(Reference::Cols(from, to)), _) => edits.push((
+            address_span(formula, node.span),
+            format!("{}:{}", from.col_text(), to.col_text()),
+        )),
+        _ => {}
+    }
+}
+
+/// 去掉工作表前缀后的地址部分，带引号的工作表名可能含有 !，因此取最后一个
+fn address_span(formula: &str, span: Span) -> Span {
+    match formula[span.start..span.end].rfind('!') {
+        Some(i) => Span::new(span.start + i + 1, span.end),
+        None => span,
+    }
+}
+
+/// 将位于 from 的公式复制到 to，返回调整后的公式文本。
+/// 相对引用按两个位置的行列差平移，绝对引用不变，平移后超出工作表的引用变为 #REF!
+pub fn relocate(formula: &str, node: &Node, from: &CellPosition, to: &CellPosition) -> String {
+    let rows = to.row as isize - from.row as isize;
+    let cols = to.col as isize - from.col as isize;
+    let cell = |cell: CellRef| {
+        Some(CellRef::new(
+            shift(cell.row, rows, MAX_ROWS)?,
+            shift(cell.col, cols, MAX_COLS)?,
+        ))
+    };
+    rewrite_references(formula, node, &mut |reference, _| match *reference {
+        Reference::Cell(c) => Some(Reference::Cell(cell(c)?)),
+        Reference::Area(a, b) => Some(Reference::Area(cell(a)?, cell(b)?)),
+        Reference::Rows(a, b) => Some(Reference::Rows(
+            shift(a, rows, MAX_ROWS)?,
+            shift(b, rows, MAX_ROWS)?,
+        )),
+        Reference::Cols(a, b) => Some(Reference::Cols(
+            shift(a, cols, MAX_COLS)?,
+            shift(b, cols, MAX_COLS)?,
+        )),
+    })
+}
+
+fn shift(axis: AxisRef, offset: isize, max: usize) -> Option<AxisRef> {
+    if axis.abs {
+        return Some(axis);
+    }
+    Some(AxisRef::new(add_offset(axis.index, offset, max)?, false))
+}
+
+/// 工作表的结构变化，行列均从 1 开始
+#[derive(Debug, Clone, PartialEq)]
+pub enum StructuralEdit {
+    /// 在第 at 行之前插入 count 行
+    InsertRows {
+        sheet: String,
+        at: usize,
+        count: usize,
+    },
+    /// 删除从第 at 行开始的 count 行
+    DeleteRows {
+        sheet: String,
+        at: usize,
+        count: usize,
+    },
+    /// 在第 at 列之前插入 count 列
+    InsertCols {
+        sheet: String,
+        at: usize,
+        count: usize,
+    },
+    /// 删除从第 at 列开始的 count 列
+    DeleteCols {
+        sheet: String,
+        at: usize,
+        count: usize,
+    },
+    /// 将区域在所在的工作表内平移 rows 行、cols 列
+    Move {
+        range: CellRange,
+        rows: isize,
+        cols: isize,
+    },
+}
+
+/// 按工作表的结构变化改写 position 处的公式，返回新的公式文本。
+/// 与复制公式不同，绝对引用同样会随之调整；引用的单元格被删除或被移动的区域覆盖时变为 #REF!。
+/// 插入或删除的起始位置或数量为 0 时公式不变
+pub fn apply_edit(
+    formula: &str,
+    node: &Node,
+    position: &CellPosition,
+    edit: &StructuralEdit,
+) -> String {
+    let (sheet, axis_edit) = match edit {
+        StructuralEdit::InsertRows { sheet, at, count } => {
+            (sheet, (AxisEdit::Insert(*at, *count), true))
+        }
+        StructuralEdit::DeleteRows { sheet, at, count } => {
+            (sheet, (AxisEdit::Delete(*at, *count), true))
+        }
+        StructuralEdit::InsertCols { sheet, at, count } => {
+            (sheet, (AxisEdit::Insert(*at, *count), false))
+        }
+        StructuralEdit::DeleteCols { sheet, at, count } => {
+            (sheet, (AxisEdit::Delete(*at, *count), false))
+        }
+        StructuralEdit::Move { range, rows, cols } => {
+            return rewrite_references(formula, node, &mut |reference, s| {
+                if !same_sheet(s.unwrap_or(&position.sheet), &range.sheet) {
+                    return Some(*reference);
+                }
+                move_reference(*reference, range, *rows, *cols)
+            });
+        }
+    };
+    let (axis_edit, on_rows) = axis_edit;
+    if axis_edit.is_noop() {
+        return formula.to_string();
+    }
+    rewrite_references(formula, node, &mut |reference, s| {
+        if !same_sheet(s.unwrap_or(&position.sheet), sheet) {
+            return Some(*reference);
+        }
+        edit_axis(*reference, axis_edit, on_rows)
+    })
+}
+
+/// 插入或删除行（列），参数为起始的行号（列号）和数量
+#[derive(Debug, Clone, Copy)]
+enum AxisEdit {
+    Insert(usize, usize),
+    Delete(usize, usize),
+}
+
+impl AxisEdit {
+    /// 起始位置或数量为 0 时不改变任何引用
+    fn is_noop(&self) -> bool {
+        match *self {
+            AxisEdit::Insert(at, count) | AxisEdit::Delete(at, count) => at == 0 || count == 0,
+        }
+    }
+
+    /// 调整单个行号或列号，被删除时返回 None
+    fn index(&self, axis: AxisRef, max: usize) -> Option<AxisRef> {
+        let index = match *self {
+            AxisEdit::Insert(at, count) if axis.index >= at => axis.index.saturating_add(count),
+            AxisEdit::Delete(at, count) if axis.index >= at.saturating_add(count) => {
+                axis.index - count
+            }
+            AxisEdit::Delete(at, _) if axis.index >= at => return None,
+            _ => axis.index,
+        };
+        if index > max {
+            return None;
+        }
+        Some(AxisRef::new(index, axis.abs))
+    }
+
+    /// 调整区域的起止行号或列号。在区域内部插入会扩大区域，删除会缩小区域，整个区域被删除时返回 None
+    fn range(&self, from: AxisRef, to: AxisRef, max: usize) -> Option<(AxisRef, AxisRef)> {
+        let (from, to) = if from.index <= to.index {
+            (from, to)
+        } else {
+            (to, from)
+        };
+        match *self {
+            AxisEdit::Insert(..) => {
+                let from = self.index(from, max)?;
+                // 区域的末端超出工作表时截断，因此 A:A 之类的整列引用插入行后保持不变
+                let to = self.index(to, max).unwrap_or(AxisRef::new(max, to.abs));
+                Some((from, to))
+            }
+            AxisEdit::Delete(at, count) => {
+                if from.index >= at && to.index < at.saturating_add(count) {
+                    return None;
+                }
+                let from = self.index(from, max).unwrap_or(AxisRef::new(at, from.abs));
+                let to = self.index(to, max).unwrap_or(AxisRef::new(at - 1, to.abs));
+                Some((from, to))
+            }
+        }
+    }
+}
+
+fn edit_axis(reference: Reference, edit: AxisEdit, on_rows: bool) -> Option<Reference> {
+    Some(match reference {
+        Reference::Cell(cell) if on_rows => {
+            Reference::Cell(CellRef::new(edit.index(cell.row, MAX_ROWS)?, cell.col))
+        }
+        Reference::Cell(cell) => {
+            Reference::Cell(CellRef::new(cell.row, edit.index(cell.col, MAX_COLS)?))
+        }
+        Reference::Area(a, b) if on_rows => {
+            let (top, bottom) = edit.range(a.row, b.row, MAX_ROWS)?;
+            Reference::Area(CellRef::new(top, a.col), CellRef::new(bottom, b.col))
+        }
+        Reference::Area(a, b) => {
+            let (left, right) = edit.range(a.col, b.col, MAX_COLS)?;
+            Reference::Area(CellRef::new(a.row, left), CellRef::new(b.row, right))
+        }
+        Reference::Rows(a, b) if on_rows => {
+            let (a, b) = edit.range(a, b, MAX_ROWS)?;
+            Reference::Rows(a, b)
+        }
+        Reference::Cols(a, b) if !on_rows => {
+            let (a, b) = edit.range(a, b, MAX_COLS)?;
+            Reference::Cols(a, b)
+        }
+        other => other,
+    })
+}
+
+/// 指向被移动区域的引用随之移动，只有整个区域都在其中时才移动；
+/// 完全落在目标位置的引用因单元格被覆盖而变为 #REF!
+fn move_reference(
+    reference: Reference,
+    range: &CellRange,
+    rows: isize,
+    cols: isize,
+) -> Option<Reference> {
+    let moved = |cell: CellRef| {
+        Some(CellRef::new(
+            AxisRef::new(add_offset(cell.row.index, rows, MAX_ROWS)?, cell.row.abs),
+            AxisRef::new(add_offset(cell.col.index, cols, MAX_COLS)?, cell.col.abs),
+        ))
+    };
+    let in_source = |cell: &CellRef| range.contains(&range.sheet, cell.row.index, cell.col.index);
+    // 目标位置即源区域平移后的位置
+    let in_target = |cell: &CellRef| {
+        let row = cell.row.index as isize - rows;
+        let col = cell.col.index as isize - cols;
+        row >= 1 && col >= 1 && range.contains(&range.sheet, row as usize, col as usize)
+    };
+    match reference {
+        Reference::Cell(cell) if in_source(&cell) => Some(Reference::Cell(moved(cell)?)),
+        Reference::Area(a, b) if in_source(&a) && in_source(&b) => {
+            Some(Reference::Area(moved(a)?, moved(b)?))
+        }
+        Reference::Cell(cell) if in_target(&cell) => None,
+        Reference::Area(a, b) if in_target(&a) && in_target(&b) => None,
+        other => Some(other),
+    }
+}
+
+fn add_offset(index: usize, offset: isize, max: usize) -> Option<usize> {
+    match (index as isize).checked_add(offset) {
+        Some(index) if index >= 1 && index <= max as isize => Some(index as usize),
+        _ => None,
+    }
+}
+
+/// 将公式中对工作表 old 的引用改为 new，工作表名按需加上引号
+pub fn rename_sheet(formula: &str, node: &Node, old: &str, new: &str) -> String {
+    let mut edits = vec![];
+    collect_sheet_edits(
+        formula,
+        node,
+        &mut |sheet| Some(if same_sheet(sheet, old) { new } else { sheet }.to_string()),
+        &[],
+        &mut edits,
+    );
+    splice(formula, edits)
+}
+
+/// 工作表 deleted 被删除后，公式中对它的引用变为 #REF!。sheets 为删除前工作表的顺序，
+/// 三维引用一端的工作表被删除时该端向内移动到范围中的下一个工作表
+pub fn delete_sheet(formula: &str, node: &Node, deleted: &str, sheets: &[String]) -> String {
+    let mut edits = vec![];
+    collect_sheet_edits(
+        formula,
+        node,
+        &mut |sheet| (!same_sheet(sheet, deleted)).then(|| sheet.to_string()),
+        sheets,
+        &mut edits,
+    );
+    splice(formula, edits)
+}
+
+/// 三维引用范围内仍然存在的第一个和最后一个工作表（按 first 到 last 的方向），
+/// 范围不在 order 中或其中的工作表都不存在时返回 None
+fn shrink_span(
+    first: &str,
+    last: &str,
+    order: &[String],
+    edit: &mut dyn FnMut(&str) -> Option<String>,
+) -> Option<(String, String)> {
+    let find = |name: &str| order.iter().position(|sheet| same_sheet(sheet, name));
+    let (a, b) = (find(first)?, find(last)?);
+    let mut kept = order[a.min(b)..=a.max(b)]
+        .iter()
+        .filter_map(|sheet| edit(sheet))
+        .collect::<Vec<_>>();
+    if a > b {
+        kept.reverse();
+    }
+    Some((kept.first()?.clone(), kept.last()?.clone()))
+}
+
+/// edit 返回工作表的新名称，返回 None 表示工作表已不存在；order 为工作表的顺序，用于调整三维引用的范围
+fn collect_sheet_edits(
+    formula: &str,
+    node: &Node,
+    edit: &mut dyn FnMut(&str) -> Option<String>,
+    order: &[String],
+    edits: &mut Vec<(Span, String)>,
+) {
+    let sheet = match &node.kind {
+        ASTNode::Ref(_, Some(sheet))
+        | ASTNode::SpillRef(_, Some(sheet))
+        | ASTNode::RowRange(_, _, Some(sheet))
+        | ASTNode::ColRange(_, _, Some(sheet))
+        | ASTNode::UndeterminedRange(sheet, _) => sheet,
+        ASTNode::Range(left, right) => {
+            // 区域任意一端的工作表不存在时，整个区域都变为 #REF!
+            for side in [left, right] {
+                if let ASTNode::Ref(_, Some(sheet)) = &side.kind {
+                    if edit(sheet).is_none() {
+                        return edits.push((node.span, ExcelError::Ref.to_string()));
+                    }
+                }
+            }
+            collect_sheet_edits(formula, left, edit, order, edits);
+            return collect_sheet_edits(formula, right, edit, order, edits);
+        }
+        ASTNode::SignedNumber(_, node) | ASTNode::ImplicitIntersection(node) => {
+            return collect_sheet_edits(formula, node, edit, order, edits)
+        }
+        // 一端的工作表不存在时该端向内移动，范围内的工作表都不存在时整个三维引用变为 #REF!
+        ASTNode::Ref3D(first, last, _) => {
+            let (new_first, new_last) = match (edit(first), edit(last)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => match shrink_span(first, last, order, edit) {
+                    Some(span) => span,
+                    None => return edits.push((node.span, ExcelError::Ref.to_string())),
+                },
+            };
+            if new_first != *first || new_last != *last {
+                let prefix = Span::new(node.span.start, address_span(formula, node.span).start);
+                edits.push((
+                    prefix,
+                    format!("{}!", quote_sheet_span(&new_first, &new_last)),
+                ));
+            }
+            return;
+        }
+        ASTNode::BinaryOp(_, left, right) => {
+            collect_sheet_edits(formula, left, edit, order, edits);
+            return collect_sheet_edits(formula, right, edit, order, edits);
+        }
+        ASTNode::Call(_, args) => {
+            for arg in args {
+                collect_sheet_edits(formula, arg, edit, order, edits);
+            }
+            return;
+        }
+        _ => return,
+    };
+    match edit(sheet) {
+        None => edits.push((node.span, ExcelError::Ref.to_string())),
+        Some(new) if new == *sheet => {}
+        Some(new) => {
+            // 工作表前缀包括其后的 !
+            let prefix = Span::new(node.span.start, address_span(formula, node.span).start);
+            edits.push((prefix, format!("{}!", quote_sheet_name(&new))));
+        }
+    }
+}
@@ -1,6 +1,7 @@
+use crate::{error::ExcelError, reference::CellRef};
 use std::mem::discriminant;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Number(f32),
     // SignedNumber,
@@ -8,10 +9,20 @@ pub enum Token {
     Minus,
     Mul,
     Div,
+    Pow,
     Lparen,
     Rparen,
+    Lbrace,
+    Rbrace,
+    Semicolon,
     // Eof,
-    Ref(String),
+    Ref(CellRef),
+    /// 紧跟在单元格地址之后的 #，表示溢出区域
+    Spill,
+    /// 前缀运算符 @，表示隐式交集
+    At,
+    /// 两个引用之间的空格，表示交集
+    Intersect,
     Var(String),
     Sheet(String),
     Ee,
@@ -26,6 +37,7 @@ pub enum Token {
     Csref,
     And,
     Bool(bool),
+    Error(ExcelError),
 }
 
 impl Token {
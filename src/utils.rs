@@ -1,4 +1,6 @@
-pub fn some<T>(list: &Vec<T>, predicate: impl Fn(&T) -> bool) -> bool {
+use crate::{range::MAX_COLS, reference::CellRef};
+
+pub fn some<T>(list: &[T], predicate: impl Fn(&T) -> bool) -> bool {
     for item in list {
         if predicate(item) {
             return true;
@@ -6,3 +8,73 @@ pub fn some<T>(list: &Vec<T>, predicate: impl Fn(&T) -> bool) -> bool {
     }
     return false;
 }
+
+/// 将列名（如 A、AB）转换为从 1 开始的列号，超出工作表的列数时返回 None
+pub fn col_index(name: &str) -> Option<usize> {
+    if name.is_empty() {
+        return None;
+    }
+    let mut index = 0_usize;
+    for c in name.chars() {
+        if !c.is_ascii_alphabetic() {
+            return None;
+        }
+        let digit = c.to_ascii_uppercase() as usize - 'A' as usize + 1;
+        index = index.checked_mul(26)?.checked_add(digit)?;
+        if index > MAX_COLS {
+            return None;
+        }
+    }
+    return Some(index);
+}
+
+/// 将从 1 开始的列号转换为列名，如 28 转换为 AB
+pub fn col_name(mut index: usize) -> String {
+    let mut name = vec![];
+    while index > 0 {
+        let rem = (index - 1) % 26;
+        name.push((b'A' + rem as u8) as char);
+        index = (index - 1) / 26;
+    }
+    return name.into_iter().rev().collect();
+}
+
+/// 按 Excel 常规格式输出数字：整数不带小数点，过大或过小的数字使用科学计数法
+pub fn format_number(num: f32) -> String {
+    let abs = num.abs();
+    if abs != 0_f32 && !(1e-9_f32..1e15_f32).contains(&abs) {
+        let text = format!("{:E}", num);
+        return match text.find("E-") {
+            Some(_) => text,
+            None => text.replace('E', "E+"),
+        };
+    }
+    return format!("{}", num);
+}
+
+/// 工作表名不是普通标识符时需要用单引号括起来，名称中的单引号写作两个单引号
+pub fn quote_sheet_name(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !name.eq_ignore_ascii_case("TRUE")
+        && !name.eq_ignore_ascii_case("FALSE")
+        // 形如 A1 的名称会被当作单元格地址
+        && CellRef::parse(name).is_none();
+    if plain {
+        return name.to_string();
+    }
+    return format!("'{}'", name.replace('\'', "''"));
+}
+
+/// 工作表名不区分大小写
+pub fn same_sheet(a: &str, b: &str) -> bool {
+    a == b || a.to_lowercase() == b.to_lowercase()
+}
+
+/// 三维引用的工作表范围，如 Jan:Dec；任意一端需要引号时整个范围一起括起来，如 'Q1:Q4'
+pub fn quote_sheet_span(first: &str, last: &str) -> String {
+    if quote_sheet_name(first) == first && quote_sheet_name(last) == last {
+        return format!("{}:{}", first, last);
+    }
+    return quote_sheet_name(&format!("{}:{}", first, last));
+}
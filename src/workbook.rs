@@ -0,0 +1,900 @@
+use crate::{
+    error::{Error, ExcelError},
+    interpreter::{CellPosition, ComputeResult, Interpreter, MAX_NAME_DEPTH},
+    names::NameManager,
+    node::{ASTNode, Node},
+    provider::DataProvider,
+    range::{CellRange, MAX_COLS, MAX_ROWS},
+    utils::same_sheet,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+struct Cell {
+    formula: Option<Node>,
+    value: ComputeResult,
+}
+
+/// 动态数组公式的溢出区域，左上角为公式所在的单元格
+#[derive(Debug, Clone, PartialEq)]
+struct Spill {
+    range: CellRange,
+    /// 区域内有其他内容或超出工作表，此时公式的结果为 #SPILL!
+    blocked: bool,
+}
+
+#[derive(Default)]
+struct Sheet {
+    cells: HashMap<(usize, usize), Cell>,
+    max_row: usize,
+    max_col: usize,
+}
+
+/// 工作簿：保存单元格的值与公式，并维护公式之间的依赖关系。
+/// 编辑单元格后只重算受影响的公式，按依赖顺序求值
+#[derive(Default)]
+pub struct Workbook {
+    interpreter: Interpreter,
+    /// 工作表名不区分大小写，以小写的名称为键
+    sheets: HashMap<String, Sheet>,
+    /// 公式单元格引用的区域
+    precedents: HashMap<CellPosition, Vec<CellRange>>,
+    /// 单个单元格 -> 直接引用它的公式单元格
+    dependents: HashMap<CellPosition, HashSet<CellPosition>>,
+    /// 多单元格区域 -> 引用它的公式单元格
+    range_dependents: RangeIndex,
+    /// 包含易失函数的公式单元格，每次重算都需要求值
+    volatile: HashSet<CellPosition>,
+    /// 当前存在的循环引用
+    cycles: Vec<Vec<CellPosition>>,
+    /// 结果为数组的公式单元格 -> 溢出区域，被阻挡时同样记录所需的区域
+    spills: HashMap<CellPosition, Spill>,
+    /// 溢出区域中的单元格（不含左上角）-> 溢出区域左上角的公式单元格
+    spilled: HashMap<CellPosition, CellPosition>,
+    /// 工作表的顺序，按添加或首次写入的先后排列；单元格位置中的工作表名统一为这里的写法
+    sheet_order: Vec<String>,
+    settings: CalcSettings,
+}
+
+/// 计算选项，对应 Excel 的“启用迭代计算”
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalcSettings {
+    /// 是否允许循环引用通过迭代求值，关闭时循环引用中的单元格取 0
+    pub iterative: bool,
+    pub max_iterations: usize,
+    /// 两次迭代之间所有单元格的最大变化量小于该值时停止迭代
+    pub max_change: f32,
+}
+
+/// 溢出区域变化引起的重算最多进行的轮数，避免溢出区域之间相互影响时无限重算
+const MAX_SPILL_PASSES: usize = 8;
+
+impl Default for CalcSettings {
+    fn default() -> Self {
+        CalcSettings {
+            iterative: false,
+            max_iterations: 100,
+            max_change: 0.001,
+        }
+    }
+}
+
+/// 区域索引中每一段的行数
+const BUCKET_ROWS: usize = 256;
+/// 跨越的段数超过该值的区域（如整列）不分段，查找时逐个检查
+const MAX_BUCKETS: usize = 64;
+
+/// 多单元格区域的索引，按工作表和行分段，查找时只检查与所在段重叠的区域
+#[derive(Default)]
+struct RangeIndex {
+    buckets: HashMap<(String, usize), Vec<(CellRange, CellPosition)>>,
+    wide: Vec<(CellRange, CellPosition)>,
+}
+
+impl RangeIndex {
+    /// 区域跨越的段，段数过多时为 None
+    fn buckets(range: &CellRange) -> Option<std::ops::RangeInclusive<usize>> {
+        let (first, last) = (range.top / BUCKET_ROWS, range.bottom / BUCKET_ROWS);
+        (last - first < MAX_BUCKETS).then_some(first..=last)
+    }
+
+    fn insert(&mut self, range: CellRange, position: CellPosition) {
+        match Self::buckets(&range) {
+            Some(buckets) => {
+                for bucket in buckets {
+                    self.buckets
+                        .entry((range.sheet.clone(), bucket))
+                        .or_default()
+                        .push((range.clone(), position.clone()));
+                }
+            }
+            None => self.wide.push((range, position)),
+        }
+    }
+
+    fn remove(&mut self, range: &CellRange, position: &CellPosition) {
+        let other = |(r, p): &(CellRange, CellPosition)| r != range || p != position;
+        match Self::buckets(range) {
+            Some(buckets) => {
+                for bucket in buckets {
+                    let key = (range.sheet.clone(), bucket);
+                    if let Some(list) = self.buckets.get_mut(&key) {
+                        list.retain(other);
+                        if list.is_empty() {
+                            self.buckets.remove(&key);
+                        }
+                    }
+                }
+            }
+            None => self.wide.retain(other),
+        }
+    }
+
+    /// 与 range 重叠的区域所对应的公式单元格
+    fn overlapping(&self, range: &CellRange, result: &mut Vec<CellPosition>) {
+        let mut key = (range.sheet.clone(), 0);
+        for bucket in range.top / BUCKET_ROWS..=range.bottom / BUCKET_ROWS {
+            key.1 = bucket;
+            for (r, position) in self.buckets.get(&key).into_iter().flatten() {
+                // 区域跨越多段时只在重叠部分的第一段中计入，避免重复
+                if r.intersect(range).is_some() && r.top.max(range.top) / BUCKET_ROWS == bucket {
+                    result.push(position.clone());
+                }
+            }
+        }
+        for (r, position) in &self.wide {
+            if r.intersect(range).is_some() {
+                result.push(position.clone());
+            }
+        }
+    }
+}
+
+impl Workbook {
+    pub fn new() -> Workbook {
+        Workbook::default()
+    }
+
+    /// 用于注册自定义函数等设置
+    pub fn interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    pub fn set_calc_settings(&mut self, settings: CalcSettings) {
+        self.settings = settings;
+    }
+
+    /// 当前存在的循环引用，每一项为构成循环的单元格
+    pub fn circular_references(&self) -> &[Vec<CellPosition>] {
+        &self.cycles
+    }
+
+    /// 工作表名不区分大小写
+    fn sheet(&self, name: &str) -> Option<&Sheet> {
+        self.sheets.get(&name.to_lowercase())
+    }
+
+    fn sheet_mut(&mut self, name: &str) -> &mut Sheet {
+        self.sheets.entry(name.to_lowercase()).or_default()
+    }
+
+    /// 将单元格位置中的工作表名统一为工作表添加时的写法
+    fn canonical(&self, mut position: CellPosition) -> CellPosition {
+        position.sheet = sheet_name(&self.sheet_order, &position.sheet);
+        position
+    }
+
+    pub fn value(&self, position: &CellPosition) -> ComputeResult {
+        self.cell(&position.sheet, position.row, position.col)
+            .unwrap_or(ComputeResult::Empty)
+    }
+
+    /// 写入常量，返回被重算的公式单元格（按求值顺序）
+    pub fn set_value(&mut self, position: CellPosition, value: ComputeResult) -> Vec<CellPosition> {
+        let mut changed = self.create_sheet(&position.sheet);
+        let position = self.canonical(position);
+        self.unlink(&position);
+        changed.extend(self.put(&position, None, value));
+        changed.push(position);
+        self.recalculate(changed)
+    }
+
+    /// 写入公式（不含开头的 =），返回被重算的公式单元格（按求值顺序），
+    /// 公式解析失败时单元格保持不变
+    pub fn set_formula(
+        &mut self,
+        position: CellPosition,
+        formula: &str,
+    ) -> Result<Vec<CellPosition>, Error> {
+        let node = self.interpreter.parse(formula)?;
+        let mut changed = self.create_sheet(&position.sheet);
+        let position = self.canonical(position);
+        self.unlink(&position);
+        self.link(&position, &node);
+        changed.extend(self.put(&position, Some(node), ComputeResult::Empty));
+        changed.push(position);
+        Ok(self.recalculate(changed))
+    }
+
+    /// 清空单元格，返回被重算的公式单元格
+    pub fn clear(&mut self, position: CellPosition) -> Vec<CellPosition> {
+        let position = self.canonical(position);
+        self.unlink(&position);
+        let mut changed = self.remove_spill(&position);
+        if let Some(sheet) = self.sheets.get_mut(&position.sheet.to_lowercase()) {
+            sheet.cells.remove(&(position.row, position.col));
+        }
+        changed.push(position);
+        self.recalculate(changed)
+    }
+
+    /// 添加空工作表，返回被重算的公式单元格。工作表按添加或首次写入的先后排列，
+    /// 三维引用一端的工作表添加之前引用为 #REF!
+    pub fn add_sheet(&mut self, name: &str) -> Vec<CellPosition> {
+        let changed = self.create_sheet(name);
+        self.recalculate(changed)
+    }
+
+    /// 工作表不存在时创建，返回依赖关系因此改变的公式单元格（三维引用包含的工作表可能变化）
+    fn create_sheet(&mut self, name: &str) -> Vec<CellPosition> {
+        if self.sheet(name).is_some() {
+            return vec![];
+        }
+        self.sheets.insert(name.to_lowercase(), Sheet::default());
+        self.sheet_order.push(name.to_string());
+        let mut changed = vec![];
+        for (position, node) in self.formulas() {
+            let mut ranges = vec![];
+            let names = self.interpreter.names();
+            collect_references(
+                &node,
+                &position.sheet,
+                names,
+                &self.sheet_order,
+                0,
+                &mut ranges,
+            );
+            if self.precedents.get(&position) != Some(&ranges) {
+                self.unlink(&position);
+                self.link(&position, &node);
+                changed.push(position);
+            }
+        }
+        changed
+    }
+
+    /// 所有公式单元格及其语法树
+    fn formulas(&self) -> Vec<(CellPosition, Node)> {
+        let mut formulas = vec![];
+        for sheet in &self.sheet_order {
+            for ((row, col), cell) in &self.sheets[&sheet.to_lowercase()].cells {
+                if let Some(node) = &cell.formula {
+                    let position = CellPosition {
+                        sheet: sheet.clone(),
+                        row: *row,
+                        col: *col,
+                    };
+                    formulas.push((position, node.clone()));
+                }
+            }
+        }
+        formulas
+    }
+
+    /// 定义名称（参见 Interpreter::define_name），并重算所有公式
+    pub fn define_name(
+        &mut self,
+        name: &str,
+        scope: Option<&str>,
+        formula: &str,
+    ) -> Result<Vec<CellPosition>, Error> {
+        self.interpreter.define_name(name, scope, formula)?;
+        Ok(self.relink_all())
+    }
+
+    /// 删除名称，并重算所有公式
+    pub fn remove_name(&mut self, name: &str, scope: Option<&str>) -> Vec<CellPosition> {
+        self.interpreter.names_mut().remove(name, scope);
+        self.relink_all()
+    }
+
+    /// 名称的定义变化后，公式通过名称引用的单元格也随之变化，需要重建所有依赖关系
+    fn relink_all(&mut self) -> Vec<CellPosition> {
+        for (position, node) in &self.formulas() {
+            self.unlink(position);
+            self.link(position, node);
+        }
+        self.recalculate_all()
+    }
+
+    /// 重算所有公式
+    pub fn recalculate_all(&mut self) -> Vec<CellPosition> {
+        let formulas = self.precedents.keys().cloned().collect();
+        self.recalculate(formulas)
+    }
+
+    /// 写入单元格，返回原有溢出区域中的单元格
+    fn put(
+        &mut self,
+        position: &CellPosition,
+        formula: Option<Node>,
+        value: ComputeResult,
+    ) -> Vec<CellPosition> {
+        let spilled = self.remove_spill(position);
+        let sheet = self.sheet_mut(&position.sheet);
+        sheet.max_row = sheet.max_row.max(position.row);
+        sheet.max_col = sheet.max_col.max(position.col);
+        sheet
+            .cells
+            .insert((position.row, position.col), Cell { formula, value });
+        spilled
+    }
+
+    /// 移除以 anchor 为左上角的溢出区域，返回区域中原有的单元格（不含左上角）
+    fn remove_spill(&mut self, anchor: &CellPosition) -> Vec<CellPosition> {
+        match self.spills.remove(anchor) {
+            Some(spill) if !spill.blocked => {
+                let cells = spill_cells(&spill.range, anchor);
+                for cell in &cells {
+                    self.spilled.remove(cell);
+                }
+                cells
+            }
+            _ => vec![],
+        }
+    }
+
+    /// 单元格中有值、公式或其他公式溢出的结果
+    fn is_occupied(&self, position: &CellPosition) -> bool {
+        self.spilled.contains_key(position)
+            || self
+                .sheet(&position.sheet)
+                .and_then(|sheet| sheet.cells.get(&(position.row, position.col)))
+                .is_some_and(|cell| cell.formula.is_some() || cell.value != ComputeResult::Empty)
+    }
+
+    /// 将数组溢出到以 anchor 为左上角的区域，区域内有其他内容或超出工作表时结果为 #SPILL!
+    fn spill(&mut self, anchor: &CellPosition, rows: Vec<Vec<ComputeResult>>) -> ComputeResult {
+        let height = rows.len();
+        let width = rows.first().map_or(0, |row| row.len());
+        if height == 0 || width == 0 {
+            return ComputeResult::Error(ExcelError::Value);
+        }
+        let bottom = anchor.row + height - 1;
+        let right = anchor.col + width - 1;
+        let range = CellRange::new(
+            anchor.sheet.clone(),
+            (anchor.row, anchor.col),
+            (bottom.min(MAX_ROWS), right.min(MAX_COLS)),
+        );
+        let cells = spill_cells(&range, anchor);
+        let blocked = bottom > MAX_ROWS
+            || right > MAX_COLS
+            || cells.iter().any(|cell| self.is_occupied(cell));
+        self.spills.insert(anchor.clone(), Spill { range, blocked });
+        if blocked {
+            return ComputeResult::Error(ExcelError::Spill);
+        }
+        for cell in cells {
+            self.spilled.insert(cell, anchor.clone());
+        }
+        let sheet = self.sheet_mut(&anchor.sheet);
+        sheet.max_row = sheet.max_row.max(bottom);
+        sheet.max_col = sheet.max_col.max(right);
+        ComputeResult::Array(rows)
+    }
+
+    /// 记录公式引用的单元格和区域
+    fn link(&mut self, position: &CellPosition, node: &Node) {
+        let mut ranges = vec![];
+        let names = self.interpreter.names();
+        collect_references(
+            node,
+            &position.sheet,
+            names,
+            &self.sheet_order,
+            0,
+            &mut ranges,
+        );
+        for range in &ranges {
+            if range.rows() == 1 && range.cols() == 1 {
+                let precedent = CellPosition {
+                    sheet: range.sheet.clone(),
+                    row: range.top,
+                    col: range.left,
+                };
+                self.dependents
+                    .entry(precedent)
+                    .or_default()
+                    .insert(position.clone());
+            } else {
+                self.range_dependents
+                    .insert(range.clone(), position.clone());
+            }
+        }
+        if self.is_volatile(node) {
+            self.volatile.insert(position.clone());
+        }
+        self.precedents.insert(position.clone(), ranges);
+    }
+
+    /// 移除单元格原有公式的依赖关系
+    fn unlink(&mut self, position: &CellPosition) {
+        let ranges = match self.precedents.remove(position) {
+            Some(ranges) => ranges,
+            None => return,
+        };
+        for range in ranges {
+            if range.rows() == 1 && range.cols() == 1 {
+                let precedent = CellPosition {
+                    sheet: range.sheet,
+                    row: range.top,
+                    col: range.left,
+                };
+                if let Some(set) = self.dependents.get_mut(&precedent) {
+                    set.remove(position);
+                    if set.is_empty() {
+                        self.dependents.remove(&precedent);
+                    }
+                }
+            } else {
+                self.range_dependents.remove(&range, position);
+            }
+        }
+        self.volatile.remove(position);
+    }
+
+    fn is_volatile(&self, node: &Node) -> bool {
+        match &node.kind {
+            ASTNode::Call(name, args) => {
+                self.interpreter.functions().is_volatile(name)
+                    || args.iter().any(|arg| self.is_volatile(arg))
+            }
+            ASTNode::SignedNumber(_, node)
+            | ASTNode::UndeterminedRange(_, node)
+            | ASTNode::ImplicitIntersection(node) => self.is_volatile(node),
+            ASTNode::BinaryOp(_, left, right) | ASTNode::Range(left, right) => {
+                self.is_volatile(left) || self.is_volatile(right)
+            }
+            _ => false,
+        }
+    }
+
+    /// 引用了该单元格的公式单元格，可能有重复。单元格是动态数组公式时，还包括引用了其溢出区域的公式
+    fn referencing(&self, position: &CellPosition) -> Vec<CellPosition> {
+        let mut result = match self.dependents.get(position) {
+            Some(set) => set.iter().cloned().collect::<Vec<_>>(),
+            None => vec![],
+        };
+        let cell = CellRange::new(
+            position.sheet.clone(),
+            (position.row, position.col),
+            (position.row, position.col),
+        );
+        self.range_dependents.overlapping(&cell, &mut result);
+        if let Some(spill) = self.spills.get(position).filter(|spill| !spill.blocked) {
+            for cell in spill_cells(&spill.range, position) {
+                if let Some(set) = self.dependents.get(&cell) {
+                    result.extend(set.iter().cloned());
+                }
+            }
+            self.range_dependents.overlapping(&spill.range, &mut result);
+        }
+        result
+    }
+
+    /// 直接引用该单元格的公式单元格（参见 referencing）。
+    /// 单元格位于其他公式的溢出区域中时，还包括该公式，因为单元格的内容可能阻挡溢出
+    fn direct_dependents(&self, position: &CellPosition) -> Vec<CellPosition> {
+        let mut result = self.referencing(position);
+        for (anchor, spill) in &self.spills {
+            if anchor != position
+                && spill
+                    .range
+                    .contains(&position.sheet, position.row, position.col)
+            {
+                result.push(anchor.clone());
+            }
+        }
+        result
+    }
+
+    /// 重算 changed 中的公式及其所有直接或间接的依赖者，返回求值顺序
+    fn recalculate(&mut self, mut changed: Vec<CellPosition>) -> Vec<CellPosition> {
+        let mut order = vec![];
+        let mut seen = HashSet::new();
+        // 溢出区域变化后，引用了区域中单元格的公式需要再次重算
+        for _ in 0..MAX_SPILL_PASSES {
+            let (evaluated, spilled) = self.recalculate_once(changed);
+            for position in evaluated {
+                if seen.insert(position.clone()) {
+                    order.push(position);
+                }
+            }
+            if spilled.is_empty() {
+                break;
+            }
+            changed = spilled;
+        }
+        order
+    }
+
+    /// 按依赖顺序重算一遍，返回求值顺序和溢出区域发生变化的单元格
+    fn recalculate_once(
+        &mut self,
+        changed: Vec<CellPosition>,
+    ) -> (Vec<CellPosition>, Vec<CellPosition>) {
+        let mut dirty = HashSet::new();
+        let mut queue = VecDeque::new();
+        for position in changed.into_iter().chain(self.volatile.iter().cloned()) {
+            if self.precedents.contains_key(&position) {
+                dirty.insert(position.clone());
+            }
+            queue.push_back(position);
+        }
+        while let Some(position) = queue.pop_front() {
+            for dependent in self.direct_dependents(&position) {
+                if dirty.insert(dependent.clone()) {
+                    queue.push_back(dependent);
+                }
+            }
+        }
+        let mut order = vec![];
+        let mut spilled = vec![];
+        self.cycles
+            .retain(|cycle| !cycle.iter().any(|cell| dirty.contains(cell)));
+        for (component, cyclic) in self.components(&dirty) {
+            if !cyclic {
+                let value = self.evaluate(&component[0]);
+                spilled.extend(self.store(&component[0], value));
+            } else if self.settings.iterative {
+                spilled.extend(self.iterate(&component));
+            } else {
+                // 未开启迭代计算时，循环引用中的单元格取 0
+                for cell in &component {
+                    spilled.extend(self.store(cell, ComputeResult::Number(0_f32)));
+                }
+            }
+            if cyclic {
+                self.cycles.push(component.clone());
+            }
+            order.extend(component);
+        }
+        (order, spilled)
+    }
+
+    /// 保存公式的结果，结果为数组时溢出到相邻的单元格。
+    /// 返回溢出区域发生变化时新旧区域中的单元格
+    fn store(&mut self, position: &CellPosition, value: ComputeResult) -> Vec<CellPosition> {
+        let cell = match self
+            .sheet(&position.sheet)
+            .and_then(|sheet| sheet.cells.get(&(position.row, position.col)))
+        {
+            Some(cell) => cell,
+            None => return vec![],
+        };
+        let old_value = cell.value.clone();
+        let old_spill = self.spills.get(position).cloned();
+        let mut cells = self.remove_spill(position);
+        let value = match value {
+            ComputeResult::Array(rows) => self.spill(position, rows),
+            value => value,
+        };
+        let new_spill = self.spills.get(position).cloned();
+        if old_spill == new_spill && old_value == value {
+            return vec![];
+        }
+        if let Some(spill) = new_spill.filter(|spill| !spill.blocked) {
+            cells.extend(spill_cells(&spill.range, position));
+        }
+        if let Some(cell) = self
+            .sheets
+            .get_mut(&position.sheet.to_lowercase())
+            .and_then(|sheet| sheet.cells.get_mut(&(position.row, position.col)))
+        {
+            cell.value = value;
+        }
+        cells
+    }
+
+    /// 迭代计算循环引用中的单元格，直到最大变化量小于设定值或达到最大迭代次数
+    fn iterate(&mut self, component: &[CellPosition]) -> Vec<CellPosition> {
+        let mut spilled = vec![];
+        for _ in 0..self.settings.max_iterations {
+            let mut change = 0_f32;
+            for cell in component {
+                let value = self.evaluate(cell);
+                let diff = match (self.value(cell).as_num(), value.as_num()) {
+                    (Ok(old), Ok(new)) => (new - old).abs(),
+                    _ if self.value(cell) == value => 0_f32,
+                    _ => f32::INFINITY,
+                };
+                change = change.max(diff);
+                spilled.extend(self.store(cell, value));
+            }
+            if change < self.settings.max_change {
+                break;
+            }
+        }
+        spilled
+    }
+
+    /// 将待重算的单元格按依赖关系划分为强连通分量（Tarjan 算法），
+    /// 按求值顺序返回，被引用的分量在前。第二项表示该分量是否构成循环引用
+    fn components(&self, dirty: &HashSet<CellPosition>) -> Vec<(Vec<CellPosition>, bool)> {
+        // 排序使结果与 HashSet 的遍历顺序无关
+        let mut cells = dirty.iter().collect::<Vec<_>>();
+        cells.sort_by_key(|cell| (&cell.sheet, cell.row, cell.col));
+        let n = cells.len();
+        let indices = cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| (*cell, i))
+            .collect::<HashMap<_, _>>();
+        // edges[j] 为引用了 cells[j] 的单元格，引用了溢出区域中的单元格同样依赖溢出区域左上角的公式
+        let mut edges = vec![vec![]; n];
+        for (j, precedent) in cells.iter().enumerate() {
+            for dependent in self.referencing(precedent) {
+                if let Some(&i) = indices.get(&dependent) {
+                    edges[j].push(i);
+                }
+            }
+            edges[j].sort();
+            edges[j].dedup();
+        }
+
+        const UNVISITED: usize = usize::MAX;
+        let mut index = vec![UNVISITED; n];
+        let mut low = vec![0; n];
+        let mut on_stack = vec![false; n];
+        let mut stack = vec![];
+        let mut next_index = 0;
+        let mut result = vec![];
+        for root in 0..n {
+            if index[root] != UNVISITED {
+                continue;
+            }
+            // 用显式的栈代替递归，避免很长的引用链导致栈溢出
+            let mut calls = vec![(root, 0)];
+            index[root] = next_index;
+            low[root] = next_index;
+            next_index += 1;
+            stack.push(root);
+            on_stack[root] = true;
+            while let Some((v, i)) = calls.last().cloned() {
+                if i < edges[v].len() {
+                    calls.last_mut().unwrap().1 += 1;
+                    let w = edges[v][i];
+                    if index[w] == UNVISITED {
+                        index[w] = next_index;
+                        low[w] = next_index;
+                        next_index += 1;
+                        stack.push(w);
+                        on_stack[w] = true;
+                        calls.push((w, 0));
+                    } else if on_stack[w] {
+                        low[v] = low[v].min(index[w]);
+                    }
+                    continue;
+                }
+                calls.pop();
+                if let Some((parent, _)) = calls.last() {
+                    low[*parent] = low[*parent].min(low[v]);
+                }
+                if low[v] == index[v] {
+                    let mut component = vec![];
+                    loop {
+                        let w = stack.pop().unwrap();
+                        on_stack[w] = false;
+                        component.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    let cyclic = component.len() > 1 || edges[v].contains(&v);
+                    component.sort();
+                    result.push((
+                        component.into_iter().map(|i| cells[i].clone()).collect(),
+                        cyclic,
+                    ));
+                }
+            }
+        }
+        // Tarjan 算法先得到位于依赖链末端的分量
+        result.reverse();
+        result
+    }
+
+    fn evaluate(&self, position: &CellPosition) -> ComputeResult {
+        let formula = self
+            .sheet(&position.sheet)
+            .and_then(|sheet| sheet.cells.get(&(position.row, position.col)))
+            .and_then(|cell| cell.formula.as_ref());
+        let node = match formula {
+            Some(node) => node,
+            None => return self.value(position),
+        };
+        // 公式的结果中的空值显示为 0，如引用了空单元格的 A1 或 A1:A3
+        blank_as_zero(match self.interpreter.compute_node(node, position, self) {
+            // 旧版计算方式下公式的结果不溢出
+            Ok(value) if self.interpreter.legacy_arrays() => self
+                .interpreter
+                .implicit_intersection(value, position, self),
+            // 结果为多个单元格的区域时，和数组一样溢出
+            Ok(ComputeResult::Range(range)) => {
+                if range.rows() == 1 && range.cols() == 1 {
+                    range.get(self, 0, 0)
+                } else {
+                    ComputeResult::Array(range.values(self))
+                }
+            }
+            Ok(ComputeResult::Union(_)) => ComputeResult::Error(ExcelError::Value),
+            Ok(value) => value,
+            // 公式中有引擎尚不支持的部分
+            Err(_) => ComputeResult::Error(ExcelError::Value),
+        })
+    }
+}
+
+impl DataProvider for Workbook {
+    fn cell(&self, sheet: &str, row: usize, col: usize) -> Option<ComputeResult> {
+        let value = |row, col| {
+            self.sheet(sheet)
+                .and_then(|s| s.cells.get(&(row, col)))
+                .map(|cell| &cell.value)
+        };
+        if let Some(value) = value(row, col) {
+            // 动态数组公式所在的单元格显示数组的第一个元素，空数组视为空单元格
+            return match value {
+                ComputeResult::Array(rows) => rows.first().and_then(|row| row.first()).cloned(),
+                value => Some(value.clone()),
+            };
+        }
+        let position = CellPosition {
+            sheet: sheet_name(&self.sheet_order, sheet),
+            row,
+            col,
+        };
+        let anchor = self.spilled.get(&position)?;
+        match value(anchor.row, anchor.col)? {
+            ComputeResult::Array(rows) => rows
+                .get(row - anchor.row)
+                .and_then(|items| items.get(col - anchor.col))
+                .cloned(),
+            _ => None,
+        }
+    }
+
+    fn sheet_names(&self) -> Vec<String> {
+        self.sheet_order.clone()
+    }
+
+    fn spill_range(&self, sheet: &str, row: usize, col: usize) -> Option<CellRange> {
+        let position = CellPosition {
+            sheet: sheet_name(&self.sheet_order, sheet),
+            row,
+            col,
+        };
+        self.spills
+            .get(&position)
+            .filter(|spill| !spill.blocked)
+            .map(|spill| spill.range.clone())
+    }
+
+    fn used_range(&self, sheet: &str) -> (usize, usize) {
+        self.sheet(sheet).map_or((0, 0), |s| (s.max_row, s.max_col))
+    }
+}
+
+fn blank_as_zero(value: ComputeResult) -> ComputeResult {
+    match value {
+        ComputeResult::Empty => ComputeResult::Number(0_f32),
+        ComputeResult::Array(rows) => ComputeResult::Array(
+            rows.into_iter()
+                .map(|row| row.into_iter().map(blank_as_zero).collect())
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// 工作表添加时的写法，工作表不存在时保持原样
+fn sheet_name(order: &[String], name: &str) -> String {
+    match order.iter().find(|sheet| same_sheet(sheet, name)) {
+        Some(sheet) => sheet.clone(),
+        None => name.to_string(),
+    }
+}
+
+/// 溢出区域中除左上角以外的单元格
+fn spill_cells(range: &CellRange, anchor: &CellPosition) -> Vec<CellPosition> {
+    let mut cells = Vec::with_capacity(range.rows() * range.cols());
+    for row in range.top..=range.bottom {
+        for col in range.left..=range.right {
+            if (row, col) != (anchor.row, anchor.col) {
+                cells.push(CellPosition {
+                    sheet: range.sheet.clone(),
+                    row,
+                    col,
+                });
+            }
+        }
+    }
+    cells
+}
+
+/// 收集公式中出现的单元格和区域引用，包括通过名称引用的单元格，depth 为名称的嵌套层数；
+/// order 为工作表的顺序，用于展开三维引用
+fn collect_references(
+    node: &Node,
+    sheet: &str,
+    names: &NameManager,
+    order: &[String],
+    depth: usize,
+    ranges: &mut Vec<CellRange>,
+) {
+    let make = |s: &Option<String>, from, to| {
+        CellRange::new(sheet_name(order, s.as_deref().unwrap_or(sheet)), from, to)
+    };
+    match &node.kind {
+        // 溢出区域随左上角的公式一起变化，只需依赖该公式
+        ASTNode::Ref(cell, s) | ASTNode::SpillRef(cell, s) => {
+            ranges.push(make(s, cell.position(), cell.position()))
+        }
+        ASTNode::Range(left, right) => {
+            if let (ASTNode::Ref(from, s1), ASTNode::Ref(to, s2)) = (&left.kind, &right.kind) {
+                let s = if s1.is_some() { s1 } else { s2 };
+                return ranges.push(make(s, from.position(), to.position()));
+            }
+            // 结果是两侧引用的外接矩形，无法静态确定时按两侧出现的所有引用取外接矩形
+            let mut sides = vec![];
+            collect_references(left, sheet, names, order, depth, &mut sides);
+            collect_references(right, sheet, names, order, depth, &mut sides);
+            if let Some(first) = sides.first().cloned() {
+                let bounds = sides
+                    .iter()
+                    .try_fold(first, |bounds, range| bounds.bounding(range));
+                ranges.extend(bounds);
+            }
+            ranges.extend(sides);
+        }
+        ASTNode::RowRange(from, to, s) => {
+            ranges.push(make(s, (from.index, 1), (to.index, MAX_COLS)))
+        }
+        ASTNode::ColRange(from, to, s) => {
+            ranges.push(make(s, (1, from.index), (MAX_ROWS, to.index)))
+        }
+        ASTNode::Var(name) if depth < MAX_NAME_DEPTH => {
+            if let Some(definition) = names.resolve(name, sheet) {
+                collect_references(definition, sheet, names, order, depth + 1, ranges);
+            }
+        }
+        ASTNode::UndeterminedRange(scope, inner) if depth < MAX_NAME_DEPTH => {
+            if let ASTNode::Var(name) = &inner.kind {
+                if let Some(definition) = names.get(name, Some(scope)) {
+                    collect_references(definition, sheet, names, order, depth + 1, ranges);
+                }
+            }
+        }
+        ASTNode::SignedNumber(_, node) | ASTNode::ImplicitIntersection(node) => {
+            collect_references(node, sheet, names, order, depth, ranges)
+        }
+        ASTNode::Ref3D(first, last, inner) => {
+            let find = |name: &str| order.iter().position(|s| same_sheet(s, name));
+            if let (Some(a), Some(b)) = (find(first), find(last)) {
+                for s in &order[a.min(b)..=a.max(b)] {
+                    collect_references(inner, s, names, order, depth, ranges);
+                }
+            }
+        }
+        ASTNode::BinaryOp(_, left, right) => {
+            collect_references(left, sheet, names, order, depth, ranges);
+            collect_references(right, sheet, names, order, depth, ranges);
+        }
+        ASTNode::Call(_, args) => {
+            for arg in args {
+                collect_references(arg, sheet, names, order, depth, ranges);
+            }
+        }
+        _ => {}
+    }
+}